@@ -1,3 +1,5 @@
+use std::io::Write;
+
 use super::helper::*;
 use clap::{App, ArgMatches};
 use reqwest::Client;
@@ -7,10 +9,40 @@ use crate::{error::Error, error::Result, AppConfig};
 
 #[derive(Serialize, Deserialize, Debug)]
 struct NotifyRequest {
-    to: String,
+    to: Vec<String>,
+    cc: Vec<String>,
+    bcc: Vec<String>,
+    subject: Option<String>,
+    content_type: Option<String>,
+    body: Option<String>,
+    html: Option<String>,
+    text: Option<String>,
+    idempotency_key: Option<String>,
+    template: Option<String>,
+    template_version: Option<i32>,
+    variables: serde_json::Map<String, serde_json::Value>,
+    locale: Option<String>,
+    sender: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<String>,
+    webhook_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TemplateContent {
     subject: String,
-    content_type: String,
-    body: String,
+    html: Option<String>,
+    text: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PubTemplate {
+    name: String,
+    locale: Option<String>,
+    version: i32,
+    subject: String,
+    html: Option<String>,
+    text: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -18,6 +50,22 @@ enum NotifyStatus {
     Pending,
     Sent,
     Error,
+    Suppressed,
+    Bounced,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PubRecipientInfo {
+    address: String,
+    status: NotifyStatus,
+    error: Option<String>,
+}
+
+/// A page of the notification list, `next_cursor` is the `--after` of the next page.
+#[derive(Serialize, Deserialize, Debug)]
+struct NotifyPage {
+    items: Vec<PubNotifyInfo>,
+    next_cursor: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -25,6 +73,122 @@ struct PubNotifyInfo {
     message_id: String,
     status: NotifyStatus,
     error: Option<String>,
+    #[serde(default)]
+    recipients: Vec<PubRecipientInfo>,
+    #[serde(default)]
+    relay: Option<String>,
+    #[serde(default)]
+    next_attempt_at: Option<String>,
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    first_attempt_at: Option<String>,
+    #[serde(default)]
+    sent_at: Option<String>,
+    #[serde(default)]
+    attempts: i32,
+    #[serde(default)]
+    reply_code: Option<i32>,
+    #[serde(default)]
+    reply_text: Option<String>,
+    #[serde(default)]
+    tracking: Option<PubTracking>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PubTracking {
+    opens: i32,
+    clicks: i32,
+    first_opened_at: Option<String>,
+    first_clicked_at: Option<String>,
+    clicked_links: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct NotifyEvent {
+    message_id: String,
+    status: NotifyStatus,
+    error: Option<String>,
+    attempts: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct QueueDepth {
+    high: i64,
+    normal: i64,
+    bulk: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StatusCounts {
+    pending: i64,
+    sent: i64,
+    error: i64,
+    suppressed: i64,
+    bounced: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ErrorCount {
+    error: String,
+    count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DomainCount {
+    domain: String,
+    count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TrackingStats {
+    tracked: i64,
+    opened: i64,
+    clicked: i64,
+    open_rate: Option<f64>,
+    click_rate: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct NotifyStats {
+    pending: QueueDepth,
+    counts: StatusCounts,
+    success_rate: Option<f64>,
+    mean_time_to_send_ms: Option<f64>,
+    p95_time_to_send_ms: Option<i64>,
+    top_errors: Vec<ErrorCount>,
+    top_domains: Vec<DomainCount>,
+    #[serde(default)]
+    tracking: Option<TrackingStats>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SuppressionRequest {
+    address: String,
+    note: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PubSuppression {
+    address: String,
+    reason: String,
+    detail: Option<String>,
+    created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MailPreview {
+    message: String,
+    warnings: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum BatchQueueResult {
+    Queued(PubNotifyInfo),
+    Rejected {
+        error: String,
+    },
 }
 
 pub fn config<'s>() -> App<'s> {
@@ -38,33 +202,126 @@ pub fn config<'s>() -> App<'s> {
                 .arg("--error")
                 .arg("--sent")
                 .arg("--pending")
+                .arg("--suppressed")
+                .arg("--bounced")
                 .arg("--user=[UID], 'User's uid to be list'")
+                .arg("--sender=[SENDER] 'Only list the notifications of the sender profile'")
+                .arg("--limit=[LIMIT] 'Max number of notifications to list, 100 by default'")
+                .arg("--after=[MSG_ID] 'List the notifications after this one, the next_cursor of the previous page'")
+                .arg("--since=[TIME] 'List the notifications queued at or after the RFC 3339 time'")
+                .arg("--to=[TIME] 'List the notifications queued before the RFC 3339 time'")
+                .arg("--recipient=[ADDR] 'List the notifications to the receivers contain this text'")
+                .arg("--subject=[SUBJECT] 'List the notifications with subject contains this text'")
+                .arg("--desc 'List the newest notifications first'")
         )
         .subcommand(
-            App::new("send")
+            App::new("export")
+                .about("Export notifications as CSV or NDJSON, to the file of --output if specified")
+                .arg("--format=[FORMAT] 'csv or ndjson, csv by default'")
+                .arg("--exclude-body 'Leave out the content of the notifications'")
+                .arg("--error")
+                .arg("--sent")
+                .arg("--pending")
+                .arg("--suppressed")
+                .arg("--bounced")
+                .arg("--user=[UID], 'User's uid to export'")
+                .arg("--sender=[SENDER] 'Only export the notifications of the sender profile'")
+                .arg("--limit=[LIMIT] 'Max number of notifications to export, all of them by default'")
+                .arg("--since=[TIME] 'Export the notifications queued at or after the RFC 3339 time'")
+                .arg("--to=[TIME] 'Export the notifications queued before the RFC 3339 time'")
+                .arg("--recipient=[ADDR] 'Export the notifications to the receivers contain this text'")
+                .arg("--subject=[SUBJECT] 'Export the notifications with subject contains this text'")
+                .arg("--desc 'Export the newest notifications first'")
+        )
+        .subcommand(
+            App::new("watch")
+                .about("Watch the status changes of notifications")
+                .arg("--error")
+                .arg("--sent")
+                .arg("--suppressed")
+                .arg("--bounced")
+                .arg("--user=[UID], 'User's uid to watch'")
+                .arg("--sender=[SENDER] 'Only watch the notifications of the sender profile'")
+        )
+        .subcommand(
+            App::new("stats")
+                .about("Show notification statistics")
+                .arg("--user=[UID], 'User's uid to show statistics of'")
+                .arg("--sender=[SENDER] 'Only the notifications of the sender profile'")
+                .arg("--since=[TIME] 'Only the notifications queued at or after the RFC 3339 time'")
+                .arg("--to=[TIME] 'Only the notifications queued before the RFC 3339 time'")
+        )
+        .subcommand(
+            notify_request_args(App::new("send"))
                 .about("Send a noficiation through email")
-                // .arg("[RECEIVER_ADDR] 'Email address of the notification receiver'")
-                .arg("[BODY_FILE] 'File path to the notification body'")
-                .arg("--to=[RECEIVER_ADDR] 'Email address of the notification receiver'")
-                .arg("--subject=[SUBJECT] 'Subject of the notification mail'")
-                .arg("--content-type=[CONTENT_TYPE] 'Content-Type of the notification mail'")
-                .arg("--text=[TEXT_BODY] 'Notification body text'"),
+                .arg("--idempotency-key=[KEY] 'Idempotency key to prevent duplicated notifications on retry'")
+                .arg("--batch=[BATCH_FILE] 'Send notifications in a JSON file contains an array of notify requests'"),
+        )
+        .subcommand(
+            notify_request_args(App::new("preview"))
+                .about("Render a notification into the mail message without sending it"),
+        )
+        .subcommand(
+            App::new("template")
+                .about("Manage notification templates")
+                .subcommand(
+                    App::new("list")
+                        .about("List the latest version of all templates")
+                        .arg("--sender=[SENDER] 'Sender profile owns the templates, the default one if not specified'"),
+                )
+                .subcommand(
+                    App::new("get")
+                        .about("Get a template")
+                        .arg("<NAME> 'Name of the template'")
+                        .arg("--sender=[SENDER] 'Sender profile owns the template, the default one if not specified'")
+                        .arg("--locale=[LOCALE] 'Language tag of the template variant, the default variant if not specified'")
+                        .arg("--version=[VERSION] 'Version of the template, the latest version by default'"),
+                )
+                .subcommand(
+                    App::new("set")
+                        .about("Save a template as a new version")
+                        .arg("<NAME> 'Name of the template'")
+                        .arg("--sender=[SENDER] 'Sender profile owns the template, the default one if not specified'")
+                        .arg("--locale=[LOCALE] 'Language tag of the template variant, the default variant if not specified'")
+                        .arg("--subject=<SUBJECT> 'Subject template of the notification mail'")
+                        .arg("--html-file=[HTML_FILE] 'File path to the HTML body template'")
+                        .arg("--text-file=[TEXT_FILE] 'File path to the plain text body template'"),
+                )
+                .subcommand(
+                    App::new("delete")
+                        .about("Delete all versions of a template")
+                        .arg("<NAME> 'Name of the template'")
+                        .arg("--sender=[SENDER] 'Sender profile owns the template, the default one if not specified'")
+                        .arg("--locale=[LOCALE] 'Only delete the variant of the language tag'"),
+                ),
+        )
+        .subcommand(
+            App::new("suppression")
+                .about("Manage the recipients not to send notifications to")
+                .subcommand(
+                    App::new("list")
+                        .about("List the suppressed recipients, the latest added first")
+                        .arg("--sender=[SENDER] 'Sender profile owns the suppression list, the default one if not specified'"),
+                )
+                .subcommand(
+                    App::new("add")
+                        .about("Stop sending notifications to a recipient")
+                        .arg("<ADDR> 'Email address of the recipient'")
+                        .arg("--note=[NOTE] 'Why the recipient is suppressed'")
+                        .arg("--sender=[SENDER] 'Sender profile owns the suppression list, the default one if not specified'"),
+                )
+                .subcommand(
+                    App::new("remove")
+                        .about("Send notifications to a suppressed recipient again")
+                        .arg("<ADDR> 'Email address of the recipient'")
+                        .arg("--sender=[SENDER] 'Sender profile owns the suppression list, the default one if not specified'"),
+                ),
         )
 }
 
 pub async fn notify(cfg: AppConfig<'_>, matches: &ArgMatches) -> Result<()> {
     if let Some(matches) = matches.subcommand_matches("list") {
-        let filter = if matches.is_present("all") {
-            "All"
-        } else if matches.is_present("error") {
-            "Error"
-        } else if matches.is_present("sent") {
-            "Sent"
-        } else if matches.is_present("pending") {
-            "Pending"
-        } else {
-            "All"
-        };
+        let filter = status_filter(matches);
 
         let uid = if let Some(uid) = matches.value_of("user") {
             uid.to_string()
@@ -74,8 +331,19 @@ pub async fn notify(cfg: AppConfig<'_>, matches: &ArgMatches) -> Result<()> {
             return Err(Error::ErrorInfo("Missing user"));
         };
 
-        let result: Vec<PubNotifyInfo> = Client::new()
-            .get(&format!("{}/notify/all/{}?filter={}", cfg.url, uid, filter))
+        let result: NotifyPage = Client::new()
+            .get(&format!("{}/notify/all/{}", cfg.url, uid))
+            .query(&[
+                ("filter", Some(filter)),
+                ("sender", matches.value_of("sender")),
+                ("limit", matches.value_of("limit")),
+                ("after", matches.value_of("after")),
+                ("since", matches.value_of("since")),
+                ("until", matches.value_of("to")),
+                ("recipient", matches.value_of("recipient")),
+                ("subject", matches.value_of("subject")),
+                ("order", Some(if matches.is_present("desc") { "desc" } else { "asc" })),
+            ])
             .auth(cfg.auth)
             .send()
             .await
@@ -89,31 +357,133 @@ pub async fn notify(cfg: AppConfig<'_>, matches: &ArgMatches) -> Result<()> {
         println!("List notifications:");
         output(result, cfg.output);
 
-    } else if let Some(matches) = matches.subcommand_matches("send") {
-        let receiver = matches
-            .value_of("to")
-            .ok_or(Error::ErrorInfo("Missing receiver's mail address"))?
-            .to_string();
-        let content_type = matches
-            .value_of("content-type")
-            .unwrap_or("text/plain")
-            .to_string();
-        let subject = matches.value_of("subject").ok_or(Error::ErrorInfo("Missing notification subject"))?.to_string();
-        let body = if let Some(text) = matches.value_of("text") {
-            text.to_string()
-        } else if let Some(file) = matches.value_of("BODY_FILE") {
-            std::fs::read_to_string(file).map_err(Error::from)?
+    } else if let Some(matches) = matches.subcommand_matches("export") {
+        let uid = if let Some(uid) = matches.value_of("user") {
+            uid.to_string()
+        } else if let Some(auth) = &cfg.auth {
+            auth.uid.clone()
         } else {
-            return Err(Error::ErrorInfo("Missing notification mail body"));
+            return Err(Error::ErrorInfo("Missing user"));
         };
 
-        let request = NotifyRequest {
-            to: receiver,
-            content_type,
-            body,
-            subject,
+        let mut response = Client::new()
+            .get(&format!("{}/notify/export/{}", cfg.url, uid))
+            .query(&[
+                ("format", Some(matches.value_of("format").unwrap_or("csv"))),
+                ("exclude_body", Some(if matches.is_present("exclude-body") { "true" } else { "false" })),
+                ("filter", Some(status_filter(matches))),
+                ("sender", matches.value_of("sender")),
+                ("limit", matches.value_of("limit")),
+                ("since", matches.value_of("since")),
+                ("until", matches.value_of("to")),
+                ("recipient", matches.value_of("recipient")),
+                ("subject", matches.value_of("subject")),
+                ("order", Some(if matches.is_present("desc") { "desc" } else { "asc" })),
+            ])
+            .auth(cfg.auth)
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?;
+
+        // Written as it's received instead of going through `output`, the export may be large.
+        let mut writer: Box<dyn Write> = match cfg.output {
+            Some(file) => Box::new(std::fs::File::create(file).map_err(Error::from)?),
+            None => Box::new(std::io::stdout()),
+        };
+        while let Some(chunk) = response.chunk().await.map_err(Error::from)? {
+            writer.write_all(&chunk).map_err(Error::from)?;
+        }
+        writer.flush().map_err(Error::from)?;
+
+    } else if let Some(matches) = matches.subcommand_matches("watch") {
+        let mut response = Client::new()
+            .get(&format!("{}/notify/stream", cfg.url))
+            .query(&[
+                ("uid", matches.value_of("user")),
+                ("filter", Some(status_filter(matches))),
+                ("sender", matches.value_of("sender")),
+            ])
+            .auth(cfg.auth)
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?;
+
+        println!("Watching notifications:");
+        let mut buffer = String::new();
+        while let Some(chunk) = response.chunk().await.map_err(Error::from)? {
+            buffer.push_str(&String::from_utf8_lossy(&chunk));
+            while let Some(idx) = buffer.find("\n\n") {
+                let message: String = buffer.drain(..idx + 2).collect();
+                for data in message.lines().filter_map(|line| line.strip_prefix("data: ")) {
+                    let event: NotifyEvent = serde_json::from_str(data).map_err(Error::from)?;
+                    output(event, None);
+                }
+            }
+        }
+
+    } else if let Some(matches) = matches.subcommand_matches("stats") {
+        let uid = if let Some(uid) = matches.value_of("user") {
+            uid.to_string()
+        } else if let Some(auth) = &cfg.auth {
+            auth.uid.clone()
+        } else {
+            return Err(Error::ErrorInfo("Missing user"));
         };
 
+        let result: NotifyStats = Client::new()
+            .get(&format!("{}/notify/stats/{}", cfg.url, uid))
+            .query(&[
+                ("sender", matches.value_of("sender")),
+                ("since", matches.value_of("since")),
+                ("until", matches.value_of("to")),
+            ])
+            .auth(cfg.auth)
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?
+            .json()
+            .await
+            .map_err(Error::from)?;
+
+        println!("Notification statistics:");
+        output(result, cfg.output);
+
+    } else if let Some(matches) = matches.subcommand_matches("template") {
+        template(cfg, matches).await?;
+
+    } else if let Some(matches) = matches.subcommand_matches("suppression") {
+        suppression(cfg, matches).await?;
+
+    } else if let Some(batch_file) = matches.subcommand_matches("send").and_then(|m| m.value_of("batch")) {
+        let data = std::fs::read_to_string(batch_file).map_err(Error::from)?;
+        let requests: Vec<serde_json::Value> = serde_json::from_str(&data).map_err(Error::from)?;
+
+        let result: Vec<BatchQueueResult> = Client::new()
+            .post(&format!("{}/notify/queue/batch", cfg.url))
+            .auth(cfg.auth)
+            .json(&requests)
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?
+            .json()
+            .await
+            .map_err(Error::from)?;
+
+        println!("Notifications queued.");
+        output(result, cfg.output);
+
+    } else if let Some(matches) = matches.subcommand_matches("send") {
+        let mut request = notify_request(matches)?;
+        request.idempotency_key = matches.value_of("idempotency-key").map(|key| key.to_string());
+
         let result: PubNotifyInfo = Client::new()
             .post(&format!("{}/notify/queue", cfg.url))
             .auth(cfg.auth)
@@ -130,6 +500,31 @@ pub async fn notify(cfg: AppConfig<'_>, matches: &ArgMatches) -> Result<()> {
         println!("Notification queued.");
         output(result, cfg.output);
 
+    } else if let Some(matches) = matches.subcommand_matches("preview") {
+        let request = notify_request(matches)?;
+
+        let result: MailPreview = Client::new()
+            .post(&format!("{}/notify/preview", cfg.url))
+            .auth(cfg.auth)
+            .json(&request)
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?
+            .json()
+            .await
+            .map_err(Error::from)?;
+
+        if let Some(file) = cfg.output {
+            std::fs::write(file, &result.message).map_err(Error::from)?;
+        } else {
+            println!("{}", result.message);
+        }
+        for warning in &result.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+
     } else if let Some(msg_id) = matches.value_of("MSG_ID") {
         let result: PubNotifyInfo = Client::new()
             .get(&format!("{}/notify/{}", cfg.url, msg_id))
@@ -151,3 +546,262 @@ pub async fn notify(cfg: AppConfig<'_>, matches: &ArgMatches) -> Result<()> {
 
     Ok(())
 }
+
+async fn template(cfg: AppConfig<'_>, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("list") {
+        let result: Vec<PubTemplate> = Client::new()
+            .get(&format!("{}/notify/template", cfg.url))
+            .query(&[("sender", matches.value_of("sender"))])
+            .auth(cfg.auth)
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?
+            .json()
+            .await
+            .map_err(Error::from)?;
+
+        println!("List templates:");
+        output(result, cfg.output);
+
+    } else if let Some(matches) = matches.subcommand_matches("get") {
+        let name = matches.value_of("NAME").ok_or(Error::ErrorInfo("Missing template name"))?;
+        let result: PubTemplate = Client::new()
+            .get(&format!("{}/notify/template/{}", cfg.url, name))
+            .query(&[
+                ("locale", matches.value_of("locale")),
+                ("version", matches.value_of("version")),
+                ("sender", matches.value_of("sender")),
+            ])
+            .auth(cfg.auth)
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?
+            .json()
+            .await
+            .map_err(Error::from)?;
+
+        println!("Template found.");
+        output(result, cfg.output);
+
+    } else if let Some(matches) = matches.subcommand_matches("set") {
+        let name = matches.value_of("NAME").ok_or(Error::ErrorInfo("Missing template name"))?;
+        let read_file = |arg: &str| match matches.value_of(arg) {
+            Some(file) => std::fs::read_to_string(file).map(Some).map_err(Error::from),
+            None => Ok(None),
+        };
+        let content = TemplateContent {
+            subject: matches.value_of("subject").ok_or(Error::ErrorInfo("Missing template subject"))?.to_string(),
+            html: read_file("html-file")?,
+            text: read_file("text-file")?,
+        };
+
+        let result: PubTemplate = Client::new()
+            .put(&format!("{}/notify/template/{}", cfg.url, name))
+            .query(&[("locale", matches.value_of("locale")), ("sender", matches.value_of("sender"))])
+            .auth(cfg.auth)
+            .json(&content)
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?
+            .json()
+            .await
+            .map_err(Error::from)?;
+
+        println!("Template saved.");
+        output(result, cfg.output);
+
+    } else if let Some(matches) = matches.subcommand_matches("delete") {
+        let name = matches.value_of("NAME").ok_or(Error::ErrorInfo("Missing template name"))?;
+        Client::new()
+            .delete(&format!("{}/notify/template/{}", cfg.url, name))
+            .query(&[("locale", matches.value_of("locale")), ("sender", matches.value_of("sender"))])
+            .auth(cfg.auth)
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?;
+
+        println!("Template deleted.");
+    } else {
+        return Err(Error::ErrorInfo("Invalid arguments"));
+    }
+
+    Ok(())
+}
+
+async fn suppression(cfg: AppConfig<'_>, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("list") {
+        let result: Vec<PubSuppression> = Client::new()
+            .get(&format!("{}/notify/suppression", cfg.url))
+            .query(&[("sender", matches.value_of("sender"))])
+            .auth(cfg.auth)
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?
+            .json()
+            .await
+            .map_err(Error::from)?;
+
+        println!("List suppressed recipients:");
+        output(result, cfg.output);
+
+    } else if let Some(matches) = matches.subcommand_matches("add") {
+        let request = SuppressionRequest {
+            address: matches.value_of("ADDR").ok_or(Error::ErrorInfo("Missing address"))?.to_string(),
+            note: matches.value_of("note").map(|note| note.to_string()),
+        };
+        let result: PubSuppression = Client::new()
+            .post(&format!("{}/notify/suppression", cfg.url))
+            .query(&[("sender", matches.value_of("sender"))])
+            .auth(cfg.auth)
+            .json(&request)
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?
+            .json()
+            .await
+            .map_err(Error::from)?;
+
+        println!("Recipient suppressed.");
+        output(result, cfg.output);
+
+    } else if let Some(matches) = matches.subcommand_matches("remove") {
+        let address = matches.value_of("ADDR").ok_or(Error::ErrorInfo("Missing address"))?;
+        Client::new()
+            .delete(&format!("{}/notify/suppression/{}", cfg.url, address))
+            .query(&[("sender", matches.value_of("sender"))])
+            .auth(cfg.auth)
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?;
+
+        println!("Recipient removed from the suppression list.");
+    } else {
+        return Err(Error::ErrorInfo("Invalid arguments"));
+    }
+
+    Ok(())
+}
+
+fn notify_request_args<'s>(app: App<'s>) -> App<'s> {
+    // .arg("[RECEIVER_ADDR] 'Email address of the notification receiver'")
+    app.arg("[BODY_FILE] 'File path to the notification body'")
+        .arg("--to=[RECEIVER_ADDR] 'Email addresses of the notification receivers, separated by comma'")
+        .arg("--cc=[CC_ADDR] 'Email addresses of the Cc receivers, separated by comma'")
+        .arg("--bcc=[BCC_ADDR] 'Email addresses of the Bcc receivers, separated by comma'")
+        .arg("--subject=[SUBJECT] 'Subject of the notification mail'")
+        .arg("--content-type=[CONTENT_TYPE] 'Content-Type of the notification mail'")
+        .arg("--text=[TEXT_BODY] 'Notification body text'")
+        .arg("--html-file=[HTML_FILE] 'File path to the HTML alternative of the body'")
+        .arg("--text-file=[TEXT_FILE] 'File path to the plain text alternative of the body, generated from HTML if not provided'")
+        .arg("--template=[TEMPLATE] 'Name of the template to render the notification from'")
+        .arg("--template-version=[VERSION] 'Version of the template, use the latest version by default'")
+        .arg("--variables=[VARIABLES_FILE] 'JSON file contains the template variables'")
+        .arg("--locale=[LOCALE] 'Language tag to choose the template variant, e.g. zh-CN'")
+        .arg("--sender=[SENDER] 'Name of the sender profile, use the default one if not specified'")
+        .arg("--priority=[PRIORITY] 'Priority of the notification, high | normal | bulk'")
+        .arg("--webhook-url=[URL] 'Post the status changes of the notification to this URL'")
+}
+
+fn notify_request(matches: &ArgMatches) -> Result<NotifyRequest> {
+    let receiver = address_list(matches.value_of("to"));
+    if receiver.len() <= 0 {
+        return Err(Error::ErrorInfo("Missing receiver's mail address"));
+    }
+    let template = matches.value_of("template").map(|name| name.to_string());
+    let subject = matches.value_of("subject").map(|subject| subject.to_string());
+    let read_file = |arg: &str| match matches.value_of(arg) {
+        Some(file) => std::fs::read_to_string(file).map(Some).map_err(Error::from),
+        None => Ok(None),
+    };
+    let html = read_file("html-file")?;
+    let text = read_file("text-file")?;
+    if template.is_none() && subject.is_none() {
+        return Err(Error::ErrorInfo("Missing notification subject"));
+    }
+    let (content_type, body) = if template.is_some() || html.is_some() || text.is_some() {
+        (None, None)
+    } else {
+        let content_type = matches
+            .value_of("content-type")
+            .unwrap_or("text/plain")
+            .to_string();
+        let body = if let Some(text) = matches.value_of("text") {
+            text.to_string()
+        } else if let Some(file) = matches.value_of("BODY_FILE") {
+            std::fs::read_to_string(file).map_err(Error::from)?
+        } else {
+            return Err(Error::ErrorInfo("Missing notification mail body"));
+        };
+        (Some(content_type), Some(body))
+    };
+    let template_version = match matches.value_of("template-version") {
+        Some(version) => Some(version.parse().map_err(|_| Error::ErrorInfo("Invalid template version"))?),
+        None => None,
+    };
+    let variables = match matches.value_of("variables") {
+        Some(file) => {
+            let data = std::fs::read_to_string(file).map_err(Error::from)?;
+            serde_json::from_str(&data).map_err(Error::from)?
+        }
+        None => serde_json::Map::new(),
+    };
+
+    let request = NotifyRequest {
+        to: receiver,
+        cc: address_list(matches.value_of("cc")),
+        bcc: address_list(matches.value_of("bcc")),
+        content_type,
+        body,
+        html,
+        text,
+        subject,
+        idempotency_key: None,
+        template,
+        template_version,
+        variables,
+        locale: matches.value_of("locale").map(|locale| locale.to_string()),
+        sender: matches.value_of("sender").map(|sender| sender.to_string()),
+        priority: matches.value_of("priority").map(|priority| priority.to_string()),
+        webhook_url: matches.value_of("webhook-url").map(|url| url.to_string()),
+    };
+
+    Ok(request)
+}
+
+fn status_filter(matches: &ArgMatches) -> &'static str {
+    if matches.is_present("all") {
+        "All"
+    } else if matches.is_present("error") {
+        "Error"
+    } else if matches.is_present("sent") {
+        "Sent"
+    } else if matches.is_present("pending") {
+        "Pending"
+    } else if matches.is_present("suppressed") {
+        "Suppressed"
+    } else if matches.is_present("bounced") {
+        "Bounced"
+    } else {
+        "All"
+    }
+}
+
+fn address_list(addrs: Option<&str>) -> Vec<String> {
+    addrs
+        .map(|addrs| addrs.split(',').map(|addr| addr.trim().to_string()).filter(|addr| !addr.is_empty()).collect())
+        .unwrap_or_default()
+}
@@ -19,6 +19,26 @@ struct AccessManagerProfile {
     pub access: Access,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct Relay {
+    pub smtp_address: String,
+    pub tls: bool,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RateLimit {
+    pub max_messages: i32,
+    pub interval: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Retention {
+    pub body_days: Option<i32>,
+    pub metadata_days: Option<i32>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct NotifyProfile {
     pub smtp_address: String,
@@ -27,6 +47,26 @@ struct NotifyProfile {
     pub password: String,
     pub email_address: String,
     pub name: String,
+    #[serde(default)]
+    pub max_attachments_size: Option<i64>,
+    #[serde(default)]
+    pub sender: Option<String>,
+    #[serde(default)]
+    pub default: bool,
+    #[serde(default)]
+    pub fallback_relays: Vec<Relay>,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    #[serde(default)]
+    pub webhook_secret: Option<String>,
+    #[serde(default)]
+    pub retention: Option<Retention>,
+    #[serde(default)]
+    pub verp_domain: Option<String>,
+    #[serde(default)]
+    pub tracking: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -48,6 +88,11 @@ pub fn config<'s>() -> App<'s> {
         .arg("--add 'Add new service profile'")
         .arg("--update 'Update a service profile'")
         .arg("-d, --delete 'Delete a service from a user specific by uid'")
+        .arg("--test 'Test the SMTP connection of an email notify service'")
+        .arg("--test-to=[MAIL_ADDR] 'Send a test mail to the address when testing the connection'")
+        .arg("--purge 'Purge the notification history of a user'")
+        .arg("--older-than=[DAYS] 'Purge the notifications queued more than the days ago'")
+        .arg("--body-only 'Only strip the body of the notifications when purging'")
         .subcommand(
             App::new("notify")
                 .about("Email notification push service")
@@ -56,7 +101,18 @@ pub fn config<'s>() -> App<'s> {
                 .arg("--username=[USRNAME] 'Username used for SMTP authorization'")
                 .arg("--password=[PASSWD] 'Password used for SMTP authorization'")
                 .arg("--email-addr=[MAIL_ADDR] 'Mail address of the notification sender'")
-                .arg("--name=[NAME] 'Display name of the noficiation sender'"),
+                .arg("--name=[NAME] 'Display name of the noficiation sender'")
+                .arg("--max-attachments-size=[SIZE] 'Limit of the total size in bytes of attachments in a notification'")
+                .arg("--sender=[SENDER] 'Name to choose this profile by the sender of a notification'")
+                .arg("--default 'Use this profile for the notifications without sender'")
+                .arg("--fallback-relays=[RELAYS_FILE] 'JSON file contains an array of fallback relays tried in order'")
+                .arg("--rate-limit=[RATE] 'Max messages sent in an interval of seconds, e.g. 30/60'")
+                .arg("--webhook-url=[URL] 'Post the status changes of the notifications to this URL'")
+                .arg("--webhook-secret=[SECRET] 'Key to sign the webhook events with HMAC-SHA256'")
+                .arg("--retain-body-days=[DAYS] 'Strip the body of notifications after days'")
+                .arg("--retain-days=[DAYS] 'Delete notifications after days'")
+                .arg("--verp-domain=[DOMAIN] 'Send with the envelope sender bounce+<message_id>@<DOMAIN> to match the bounces'")
+                .arg("--tracking 'Track the opens and clicks of the HTML notifications'"),
         )
         .subcommand(
             App::new("access")
@@ -117,6 +173,60 @@ pub async fn service(cfg: AppConfig<'_>, matches: &ArgMatches) -> Result<()> {
 
         println!("Service profile deleted.");
         output(result, cfg.output);
+    } else if matches.is_present("test") {
+        let uid = matches
+            .value_of("user")
+            .ok_or(Error::ErrorInfo("Missing 'user'"))?;
+        let service_id = matches
+            .value_of("service")
+            .ok_or(Error::ErrorInfo("Missing 'service'"))?;
+
+        let result: serde_json::Value = Client::new()
+            .post(&format!(
+                "{}/service/profile/{}/{}/test",
+                cfg.url, uid, service_id
+            ))
+            .auth(cfg.auth)
+            .json(&serde_json::json!({ "to": matches.value_of("test-to") }))
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?
+            .json()
+            .await
+            .map_err(Error::from)?;
+
+        println!("Connection test finished.");
+        output(result, cfg.output);
+    } else if matches.is_present("purge") {
+        let uid = matches
+            .value_of("user")
+            .ok_or(Error::ErrorInfo("Missing 'user'"))?;
+        let older_than_days: i32 = matches
+            .value_of("older-than")
+            .ok_or(Error::ErrorInfo("Missing 'older-than'"))?
+            .parse()
+            .map_err(|_| Error::ErrorInfo("Invalid 'older-than'"))?;
+
+        let result: serde_json::Value = Client::new()
+            .post(&format!("{}/service/profile/{}/purge", cfg.url, uid))
+            .auth(cfg.auth)
+            .json(&serde_json::json!({
+                "older_than_days": older_than_days,
+                "body_only": matches.is_present("body-only"),
+            }))
+            .send()
+            .await
+            .map_err(Error::from)?
+            .handle_error()
+            .await?
+            .json()
+            .await
+            .map_err(Error::from)?;
+
+        println!("Notification history purged.");
+        output(result, cfg.output);
     } else if matches.is_present("update") {
         let uid = matches
             .value_of("user")
@@ -214,6 +324,40 @@ fn build_service_profile(matches: &ArgMatches) -> Result<Service> {
                 .value_of("name")
                 .ok_or(Error::ErrorInfo("Missing 'name'"))?
                 .to_string(),
+            max_attachments_size: match matches.value_of("max-attachments-size") {
+                Some(size) => Some(size.parse().map_err(|_| Error::ErrorInfo("Invalid 'max-attachments-size'"))?),
+                None => None,
+            },
+            sender: matches.value_of("sender").map(|sender| sender.to_string()),
+            default: matches.is_present("default"),
+            fallback_relays: match matches.value_of("fallback-relays") {
+                Some(file) => {
+                    let data = std::fs::read_to_string(file).map_err(Error::from)?;
+                    serde_json::from_str(&data).map_err(Error::from)?
+                }
+                None => Vec::new(),
+            },
+            rate_limit: match matches.value_of("rate-limit") {
+                Some(rate) => Some(parse_rate_limit(rate).ok_or(Error::ErrorInfo("Invalid 'rate-limit'"))?),
+                None => None,
+            },
+            webhook_url: matches.value_of("webhook-url").map(|url| url.to_string()),
+            webhook_secret: matches.value_of("webhook-secret").map(|secret| secret.to_string()),
+            retention: match (matches.value_of("retain-body-days"), matches.value_of("retain-days")) {
+                (None, None) => None,
+                (body_days, metadata_days) => Some(Retention {
+                    body_days: match body_days {
+                        Some(days) => Some(days.parse().map_err(|_| Error::ErrorInfo("Invalid 'retain-body-days'"))?),
+                        None => None,
+                    },
+                    metadata_days: match metadata_days {
+                        Some(days) => Some(days.parse().map_err(|_| Error::ErrorInfo("Invalid 'retain-days'"))?),
+                        None => None,
+                    },
+                }),
+            },
+            verp_domain: matches.value_of("verp-domain").map(|domain| domain.to_string()),
+            tracking: matches.is_present("tracking"),
         };
         Ok(Service::EmailNotify(profile))
     } else if let Some(matches) = matches.subcommand_matches("access") {
@@ -237,3 +381,12 @@ fn build_service_profile(matches: &ArgMatches) -> Result<Service> {
         Err(Error::ErrorInfo("Unknown service type"))
     }
 }
+
+/// Parse `<max_messages>/<interval>`, e.g. `30/60` for 30 messages per minute.
+fn parse_rate_limit(rate: &str) -> Option<RateLimit> {
+    let idx = rate.find('/')?;
+    Some(RateLimit {
+        max_messages: rate[..idx].trim().parse().ok()?,
+        interval: rate[idx + 1..].trim().parse().ok()?,
+    })
+}
@@ -15,8 +15,9 @@ pub mod mail;
 pub mod mime;
 mod buffer;
 
-pub use crate::smtp::{SMTPClient, SMTPClientTCP, SMTPClientTLS};
+pub use crate::smtp::{Endpoint, MailReplies, SMTPClient, SMTPClientTCP, SMTPClientTLS};
 pub use crate::auth::AuthCommand;
 pub use crate::mime::MIMEBody;
 pub use crate::mail::MailBuilder;
-pub use crate::error::{Error, Result};
\ No newline at end of file
+pub use crate::error::{Error, Result};
+pub use crate::reply::Reply;
\ No newline at end of file
@@ -2,11 +2,14 @@ use std::collections::HashMap;
 
 use bytes::Bytes;
 
+use crate::mime::{MIMEBody, encode_multipart};
+
 // https://tools.ietf.org/html/rfc2822
 
 pub struct MailData {
     header: HashMap<String, String>,
     body: Bytes,
+    recipients: Vec<String>,
 }
 
 impl MailData {
@@ -14,8 +17,13 @@ impl MailData {
         MailData {
             header: HashMap::new(),
             body: Bytes::new(),
+            recipients: Vec::new(),
         }
     }
+    /// Addresses of all the To, Cc and Bcc recipients, used for the envelope.
+    pub fn recipients(&self) -> &[String] {
+        &self.recipients
+    }
     pub fn header<'s>(&'s self, key: &str) -> Option<&'s str> {
         self.header.get(key).map(|s|s.as_str())
     }
@@ -56,6 +64,7 @@ pub struct MailBuilder {
     to: Vec<MailBox>,
     cc: Vec<MailBox>,
     bc: Vec<MailBox>,
+    alternative_parts: Vec<Bytes>,
     body_parts: Vec<Bytes>,
     boundary: String,
 }
@@ -67,6 +76,7 @@ impl MailBuilder {
             to: Default::default(),
             cc: Default::default(),
             bc: Default::default(),
+            alternative_parts: Default::default(),
             body_parts: Default::default(),
             boundary: uuid::Uuid::new_v4().to_simple().to_string(),
         }
@@ -101,38 +111,49 @@ impl MailBuilder {
         self.data.set_header("Message-ID", format!("<{}>", message_id.into()));
         self
     }
+    pub fn content_language<T: Into<String>>(mut self, language: T) -> Self {
+        self.data.set_header("Content-Language", language);
+        self
+    }
     pub fn body<T: Into<Bytes>>(mut self, body: T) -> Self {
         self.body_parts.push(body.into());
         self
     }
+    /// Add an alternative representation of the content, e.g. plain text and HTML.
+    /// Alternatives should be added in the order of increasing preference.
+    pub fn alternative<T: Into<Bytes>>(mut self, body: T) -> Self {
+        self.alternative_parts.push(body.into());
+        self
+    }
     pub fn build(mut self) -> MailData {
+        for mailbox in self.to.iter().chain(self.cc.iter()).chain(self.bc.iter()) {
+            if !self.data.recipients.contains(&mailbox.address) {
+                self.data.recipients.push(mailbox.address.clone());
+            }
+        }
+
         if self.to.len() > 0 {
             self.data.set_header("To", self.to.into_mail_list());
         }
         if self.cc.len() > 0 {
             self.data.set_header("Cc", self.cc.into_mail_list());
         }
-        if self.bc.len() > 0 {
-            self.data.set_header("Bcc", self.bc.into_mail_list());
-        }
+        // Bcc recipients only exist in the envelope.
         self.data.set_header("Date", chrono::Local::now().to_rfc2822());
-        self.data.set_header("Content-Type", format!{r#"multipart/mixed; boundary={}"#, self.boundary});
-
-        let boundary_delimiter = format!("--{}\r\n", self.boundary);
-        let body_terminator = format!("--{}--\r\n", self.boundary);
-
-        let body_size: usize = self.body_parts.iter().map(|b| boundary_delimiter.as_bytes().len() + b.len()).sum();
-        let total_size = body_size + body_terminator.as_bytes().len();
-
-        let mut buffer = Vec::<u8>::with_capacity(total_size);
-
-        for part in self.body_parts {
-            buffer.extend_from_slice(boundary_delimiter.as_bytes());
-            buffer.extend_from_slice(&part);
-        }
-        buffer.extend_from_slice(body_terminator.as_bytes());
 
-        self.data.body = Bytes::from(buffer);
+        // The alternatives are nested in multipart/mixed only if there are other parts such as attachments.
+        let (subtype, parts) = match (self.alternative_parts.len(), self.body_parts.len()) {
+            (0, _) => ("mixed", self.body_parts),
+            (_, 0) => ("alternative", self.alternative_parts),
+            _ => {
+                let mut parts = Vec::with_capacity(self.body_parts.len() + 1);
+                parts.push(MIMEBody::multipart("alternative", self.alternative_parts).into());
+                parts.extend(self.body_parts);
+                ("mixed", parts)
+            }
+        };
+        self.data.set_header("Content-Type", format!{r#"multipart/{}; boundary={}"#, subtype, self.boundary});
+        self.data.body = encode_multipart(&self.boundary, parts);
 
         self.data
     }
@@ -1,9 +1,13 @@
 use bytes::Bytes;
 
+const BASE64_LINE_LENGTH: usize = 76;
+
 #[derive(Default)]
 pub struct MIMEBody {
     content_type: String,
     content_type_encoding: ContentTypeEncoding,
+    disposition: Option<ContentDisposition>,
+    content_id: Option<String>,
     body: Bytes,
 }
 
@@ -12,13 +16,44 @@ impl MIMEBody {
         MIMEBody {
             content_type: content_type.into(),
             content_type_encoding: ContentTypeEncoding::_7Bit,
+            disposition: None,
+            content_id: None,
             body: Bytes::new(),
         }
     }
+    /// A `multipart/<subtype>` body contains the parts.
+    pub fn multipart<T: Into<Bytes>>(subtype: &str, parts: Vec<T>) -> Self {
+        let boundary = uuid::Uuid::new_v4().to_simple().to_string();
+        let mut body = MIMEBody::new(format!("multipart/{}; boundary={}", subtype, boundary));
+        body.body = encode_multipart(&boundary, parts);
+        body
+    }
     pub fn text<T: Into<String>>(mut self, text: T) -> Self {
         self.body = Bytes::from(text.into());
         self
     }
+    /// Encode the data in base64 with lines wrapped in 76 characters.
+    pub fn base64<T: AsRef<[u8]>>(mut self, data: T) -> Self {
+        let encoded = base64::encode(data);
+        let mut buf = Vec::with_capacity(encoded.len() + encoded.len() / BASE64_LINE_LENGTH * 2);
+        for (idx, line) in encoded.as_bytes().chunks(BASE64_LINE_LENGTH).enumerate() {
+            if idx > 0 {
+                buf.extend_from_slice(b"\r\n");
+            }
+            buf.extend_from_slice(line);
+        }
+        self.body = Bytes::from(buf);
+        self.content_type_encoding = ContentTypeEncoding::Base64;
+        self
+    }
+    pub fn disposition(mut self, disposition: ContentDisposition) -> Self {
+        self.disposition = Some(disposition);
+        self
+    }
+    pub fn content_id<T: Into<String>>(mut self, content_id: T) -> Self {
+        self.content_id = Some(content_id.into());
+        self
+    }
     pub fn copy_from_slice(&mut self, data: &[u8]) {
         self.body = Bytes::copy_from_slice(data);
     }
@@ -33,7 +68,16 @@ impl Into<Bytes> for MIMEBody {
         buf.extend(format!("Content-Type: {}", self.content_type).as_bytes());
         buf.extend_from_slice(b"\r\n");
         buf.extend(Into::<String>::into(self.content_type_encoding).as_bytes());
-        buf.extend_from_slice(b"\r\n\r\n");
+        buf.extend_from_slice(b"\r\n");
+        if let Some(disposition) = self.disposition {
+            buf.extend(Into::<String>::into(disposition).as_bytes());
+            buf.extend_from_slice(b"\r\n");
+        }
+        if let Some(content_id) = self.content_id {
+            buf.extend(format!("Content-ID: <{}>", content_id).as_bytes());
+            buf.extend_from_slice(b"\r\n");
+        }
+        buf.extend_from_slice(b"\r\n");
         buf.extend_from_slice(&self.body);
         buf.extend_from_slice(b"\r\n\r\n");
 
@@ -41,6 +85,26 @@ impl Into<Bytes> for MIMEBody {
     }
 }
 
+/// Join the parts with boundary delimiters, see https://tools.ietf.org/html/rfc2046#section-5.1.1
+pub(crate) fn encode_multipart<T: Into<Bytes>>(boundary: &str, parts: Vec<T>) -> Bytes {
+    let boundary_delimiter = format!("--{}\r\n", boundary);
+    let body_terminator = format!("--{}--\r\n", boundary);
+
+    let parts: Vec<Bytes> = parts.into_iter().map(|part| part.into()).collect();
+    let body_size: usize = parts.iter().map(|b| boundary_delimiter.as_bytes().len() + b.len()).sum();
+    let total_size = body_size + body_terminator.as_bytes().len();
+
+    let mut buffer = Vec::<u8>::with_capacity(total_size);
+
+    for part in parts {
+        buffer.extend_from_slice(boundary_delimiter.as_bytes());
+        buffer.extend_from_slice(&part);
+    }
+    buffer.extend_from_slice(body_terminator.as_bytes());
+
+    Bytes::from(buffer)
+}
+
 pub enum ContentTypeEncoding {
     _7Bit,
     _8Bit,
@@ -68,7 +132,45 @@ impl Into<String> for ContentTypeEncoding {
             ContentTypeEncoding::IetfToken => "ietf-token",
             ContentTypeEncoding::XToken => "x-token",
         };
-        format!("Content-Type-Encoding: {}", encode)
+        format!("Content-Transfer-Encoding: {}", encode)
+    }
+}
+
+pub enum ContentDisposition {
+    /// (filename)
+    Inline(Option<String>),
+    /// (filename)
+    Attachment(Option<String>),
+}
+
+impl Into<String> for ContentDisposition {
+    fn into(self) -> String {
+        let (disposition, filename) = match self {
+            ContentDisposition::Inline(filename) => ("inline", filename),
+            ContentDisposition::Attachment(filename) => ("attachment", filename),
+        };
+        match filename {
+            Some(filename) => format!("Content-Disposition: {}; {}", disposition, filename_param(&filename)),
+            None => format!("Content-Disposition: {}", disposition),
+        }
+    }
+}
+
+// https://tools.ietf.org/html/rfc2231#section-4
+fn filename_param(filename: &str) -> String {
+    let quotable = filename.chars().all(|c| c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\');
+    if quotable {
+        format!(r#"filename="{}""#, filename)
+    } else {
+        let mut encoded = String::with_capacity(filename.len() * 3);
+        for byte in filename.bytes() {
+            if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+                encoded.push(byte as char);
+            } else {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        format!("filename*=UTF-8''{}", encoded)
     }
 }
 
@@ -3,7 +3,7 @@ use std::str::Utf8Error;
 use crate::{buffer::Buffer, error::Error as SMTPError, smtp::FromStream};
 use std::{num::ParseIntError, io::{self, Read}, ops::Range, str};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Reply {
     pub code: u16,
     pub text_lines: Vec<String>,
@@ -17,6 +17,13 @@ impl Reply {
             Ok(self)
         }
     }
+    pub fn is_positive_completion(&self) -> bool {
+        self.code >= 200 && self.code < 300
+    }
+    /// The command failed temporarily and could be retried later.
+    pub fn is_transient_negative(&self) -> bool {
+        self.code >= 400 && self.code < 500
+    }
 }
 
 impl FromStream<Reply, ParseError> for Reply {
@@ -9,37 +9,55 @@ use super::error::{Error as SMTPError, Result as SMTPResult};
 const SMTP_DEFAULT_PORT: u16 = 25;
 const SMTP_DEFAULT_TLS_PORT: u16 = 465;
 
+/// Replies of sending a mail to multiple recipients.
+#[derive(Debug)]
+pub struct MailReplies {
+    /// The reply of `RCPT` of each recipient.
+    pub recipients: Vec<Reply>,
+    /// The final reply of the mail data, only if any of the recipients is accepted.
+    pub data: Option<Reply>,
+}
+
 pub struct SMTPInner<S: Stream> {
     stream: S,
+    greeting: Reply,
     supported_extensions: HashMap<String, Option<String>>,
 }
 
 impl<S: Stream> SMTPInner<S> {
-    pub fn init_from_stream(mut stream: S, helo_domain: &str) -> SMTPResult<SMTPInner<S>> {
+    pub fn init_from_stream(stream: S, helo_domain: &str) -> SMTPResult<SMTPInner<S>> {
+        let mut client = Self::greet(stream)?;
+        client.ehlo(helo_domain)?;
+        Ok(client)
+    }
+    pub fn greet(mut stream: S) -> SMTPResult<SMTPInner<S>> {
         let greet = Reply::from_stream(&mut stream).map_err(SMTPError::from)?;
         if greet.code != 220 {
             return Err(SMTPError::HandshakeError(Box::new(SMTPError::ErrorReply(greet))))
         }
 
-        let mut client = Self {
+        Ok(Self {
             stream: stream,
+            greeting: greet,
             supported_extensions: HashMap::new(),
-        };
-
-        let reply = client.send_command(Command::EHLO(helo_domain.to_string())).map_err(SMTPError::from)?;
+        })
+    }
+    pub fn ehlo(&mut self, helo_domain: &str) -> SMTPResult<()> {
+        let reply = self.send_command(Command::EHLO(helo_domain.to_string())).map_err(SMTPError::from)?;
         if reply.code != 250 {
-            return Err(SMTPError::HandshakeError(Box::new(SMTPError::ErrorReply(greet))))
+            return Err(SMTPError::HandshakeError(Box::new(SMTPError::ErrorReply(reply))))
         }
 
+        self.supported_extensions.clear();
         for line in reply.text_lines.into_iter().skip(1) {
             if let Some((name, params_text)) = line.split_once(' ') {
-                client.supported_extensions.insert(name.to_string(), Some(params_text.to_string()));
+                self.supported_extensions.insert(name.to_string(), Some(params_text.to_string()));
             } else {
-                client.supported_extensions.insert(line, None);
+                self.supported_extensions.insert(line, None);
             }
         }
 
-        Ok(client)
+        Ok(())
     }
     pub fn send_command<T: SMTPCommand>(&mut self, cmd: T) -> SMTPResult<Reply> {
         cmd.write_to(&mut self.stream).map_err(SMTPError::from)?;
@@ -125,16 +143,41 @@ impl SMTPClient<SslStream<TcpStream>> {
     }
 
     fn init_tls(stream: TcpStream, host: &str) -> SMTPResult<SMTPClient<SslStream<TcpStream>>> {
+        let stream = Self::tls_handshake(stream, host)?;
+        let inner = SMTPInner::init_from_stream(stream, "localhost")?;
+        Ok(SMTPClient(inner))
+    }
+
+    /// Perform the TLS handshake on a connected stream and verify the certificate of `host`.
+    pub fn tls_handshake(stream: TcpStream, host: &str) -> SMTPResult<SslStream<TcpStream>> {
         let connector = SslConnector::builder(SslMethod::tls())
             .map_err(SMTPError::from)?
             .build();
-        let stream = connector.connect(host, stream).map_err(SMTPError::from)?;
-        let inner = SMTPInner::init_from_stream(stream, "localhost")?;
-        Ok(SMTPClient(inner))
+        connector.connect(host, stream).map_err(SMTPError::from)
     }
 }
 
 impl<S: Stream> SMTPClient<S> {
+
+    /// Read the greeting of the server from a connected stream,
+    /// `ehlo` must be sent before any other command.
+    pub fn greet(stream: S) -> SMTPResult<SMTPClient<S>> {
+        Ok(SMTPClient(SMTPInner::greet(stream)?))
+    }
+
+    pub fn ehlo(&mut self, helo_domain: &str) -> SMTPResult<&mut Self> {
+        self.0.ehlo(helo_domain)?;
+        Ok(self)
+    }
+
+    pub fn greeting(&self) -> &Reply {
+        &self.0.greeting
+    }
+
+    /// Extensions advertised in the reply of `EHLO`, with their parameters.
+    pub fn extensions(&self) -> &HashMap<String, Option<String>> {
+        &self.0.supported_extensions
+    }
     
     fn set_default_port<'s, A: Into<Endpoint>>(addr: A, default: u16) -> (String, u16) {
         let mut addr: Endpoint = addr.into();
@@ -161,6 +204,34 @@ impl<S: Stream> SMTPClient<S> {
         
         Ok(self)
     }
+    /// Send a mail to multiple recipients, return the reply of `RCPT` of each recipient and the reply of the mail data.
+    /// The mail data is only sent if any of the recipients is accepted.
+    pub fn send_mail<F: Into<String>, T: AsRef<str>, D: Into<Bytes>>(&mut self, mail_from: F, rcpt_to: &[T], data: D) -> SMTPResult<MailReplies> {
+        self.0.send_command(Command::RSET)?.expect_code(250)?;
+        self.0.send_command(Command::MAIL(mail_from.into()))?.expect_code(250)?;
+
+        let mut replies = Vec::with_capacity(rcpt_to.len());
+        for rcpt in rcpt_to {
+            replies.push(self.0.send_command(Command::RCPT(rcpt.as_ref().to_string()))?);
+        }
+
+        let data = if replies.iter().any(|reply| reply.is_positive_completion()) {
+            self.0.send_command(Command::DATABegin)?.expect_code(354)?;
+            Some(self.0.send_command(Command::DATAContent(data.into()))?.expect_code(250)?)
+        } else {
+            None
+        };
+
+        Ok(MailReplies {
+            recipients: replies,
+            data,
+        })
+    }
+    pub fn noop(&mut self) -> SMTPResult<&mut Self> {
+        self.0.send_command(Command::NOOP)?.expect_code(250)?;
+
+        Ok(self)
+    }
     pub fn quit(&mut self) -> SMTPResult<&mut Self> {
         self.0.send_command(Command::QUIT)?.expect_code(221)?;
 
@@ -172,24 +243,58 @@ impl<S: Stream> SMTPClient<S> {
 pub type SMTPClientTCP = SMTPClient<TcpStream>;
 pub type SMTPClientTLS = SMTPClient<SslStream<TcpStream>>;
 
+/// Address of an SMTP server, `host`, `host:port`, `[ipv6]` or `[ipv6]:port`.
 pub struct Endpoint {
     host: String,
     port: u16,
 }
 
-impl<T: Into<String>> From<T> for Endpoint{
-    fn from(addr: T) -> Self {
-        let addr: String = addr.into();
-        if let Some((host, port)) = addr.split_once(':') {
-            Endpoint {
-                host: host.to_string(),
-                port: port.parse().expect("Invalid port number"),
+impl Endpoint {
+    pub fn parse(addr: &str) -> SMTPResult<Endpoint> {
+        let invalid = || SMTPError::OtherError(format!("Invalid address {}", addr));
+        let (host, port) = if let Some(rest) = addr.strip_prefix('[') {
+            let end = rest.find(']').ok_or_else(invalid)?;
+            match &rest[end + 1..] {
+                "" => (&rest[..end], None),
+                port => (&rest[..end], Some(port.strip_prefix(':').ok_or_else(invalid)?)),
             }
         } else {
-            Endpoint {
-                host: addr,
-                port: 0,
+            match addr.split_once(':') {
+                // An IPv6 address without port.
+                Some(_) if addr.matches(':').count() > 1 => (addr, None),
+                Some((host, port)) => (host, Some(port)),
+                None => (addr, None),
             }
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid())?,
+            None => 0,
+        };
+        Ok(Endpoint {
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The port given in the address, or the default port of SMTP or SMTP over TLS.
+    pub fn port_or_default(&self, tls: bool) -> u16 {
+        match self.port {
+            0 if tls => SMTP_DEFAULT_TLS_PORT,
+            0 => SMTP_DEFAULT_PORT,
+            port => port,
         }
     }
+}
+
+impl<T: Into<String>> From<T> for Endpoint{
+    fn from(addr: T) -> Self {
+        Endpoint::parse(&addr.into()).expect("Invalid address")
+    }
 }
\ No newline at end of file
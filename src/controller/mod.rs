@@ -3,6 +3,10 @@ mod access_check;
 mod extractor;
 mod notify;
 mod service;
+mod suppression;
+mod template;
+mod tracking;
+mod unsubscribe;
 
 use crate::middleware;
 use crate::model;
@@ -13,6 +17,15 @@ use model::{AccessManagerProfile, NotifyProfile, ServiceManagerProfile};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
+        // Visited by the recipients, exempted from the authentication in `middleware::auth`.
+        web::scope("/unsubscribe")
+            .configure(unsubscribe::config),
+    )
+    .service(
+        web::scope("/track")
+            .configure(tracking::config),
+    )
+    .service(
         web::scope("/access")
             .wrap(service_guard::<AccessManagerProfile, _, _>())
             .configure(access::config),
@@ -22,6 +35,16 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .wrap(service_guard::<ServiceManagerProfile, _, _>())
             .configure(service::config),
     )
+    .service(
+        web::scope("/notify/template")
+            .wrap(service_guard::<NotifyProfile, _, _>())
+            .configure(template::config),
+    )
+    .service(
+        web::scope("/notify/suppression")
+            .wrap(service_guard::<NotifyProfile, _, _>())
+            .configure(suppression::config),
+    )
     .service(
         web::scope("/notify")
             .wrap(service_guard::<NotifyProfile, _, _>())
@@ -1,28 +1,199 @@
-use actix_web::{Result, error as web_errors, get, post, web::Data, web::Json, web::Path, web::{Query, ServiceConfig}};
-use model::MailData;
+use std::{collections::HashMap, time::{Duration, SystemTime}};
+
+use actix_web::{HttpRequest, HttpResponse, Result, error as web_errors, get, post, web::Bytes, web::Data, web::Json, web::Path, web::{JsonConfig, Query, ServiceConfig}};
+use futures::{future, stream, StreamExt};
+use model::{Attachment, AttachmentDisposition, MailData};
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use super::access_check::AccessCheckUtils;
 
-use crate::model::{self, EmailNotify, ExtractProfile, NotifyProfile, NotifyState, UserProfile, Service};
+use crate::model::{self, EmailNotify, ExtractProfile, NotifyPriority, NotifyProfile, NotifyQuery, NotifyState, NotifyStatusKind, NotifyTemplate, Suppression, TemplateRef, UserProfile, Service};
+use crate::service::{Escape, Locale, NotifyEvent, Template, html_to_text, is_public_webhook_url};
+use crate::utils::one_or_many;
 
 use super::extractor::ExtensionMove;
 
 #[derive(Deserialize)]
 struct NotifyRequest {
-    to: String,
-    subject: String,
-    content_type: String,
-    body: String,
+    #[serde(deserialize_with = "one_or_many")]
+    to: Vec<String>,
+    #[serde(default)]
+    cc: Vec<String>,
+    #[serde(default)]
+    bcc: Vec<String>,
+    subject: Option<String>,
+    content_type: Option<String>,
+    body: Option<String>,
+    /// The HTML alternative, a text alternative is generated from it if `text` is not provided.
+    html: Option<String>,
+    text: Option<String>,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    idempotency_key: Option<String>,
+    /// Name of the template to render the subject and body from.
+    template: Option<String>,
+    /// Use the latest version of the template if not specified.
+    template_version: Option<i32>,
+    #[serde(default)]
+    variables: Map<String, Value>,
+    /// Language tag to choose the template variant and format the variables.
+    locale: Option<String>,
+    /// Name of the sender profile, use the default one if not specified.
+    sender: Option<String>,
+    /// `high`, `normal` or `bulk`, pending notifications of higher priority are sent first.
+    /// `normal` if not specified or `null`.
+    priority: Option<NotifyPriority>,
+    /// Receives the status changes of this notification instead of the webhook of the sender profile.
+    webhook_url: Option<String>,
+}
+
+impl NotifyRequest {
+    fn validate(&self, profile: &NotifyProfile) -> std::result::Result<(), &'static str> {
+        let recipients = || self.to.iter().chain(self.cc.iter()).chain(self.bcc.iter());
+        let count = recipients().count();
+        if count == 0 {
+            Err("Missing receiver address")
+        } else if count > RECIPIENTS_MAX_COUNT {
+            Err("Too many receivers")
+        } else if !recipients().all(|addr| is_valid_address(addr)) {
+            Err("Invalid receiver address")
+        } else if self.subject.is_none() {
+            Err("Missing subject")
+        } else if self.body.is_none() && self.html.is_none() && self.text.is_none() {
+            Err("Missing body")
+        } else if self.body.is_some() && (self.html.is_some() || self.text.is_some()) {
+            Err("Body cannot be used with html or text")
+        } else if self.subject.as_deref().unwrap_or_default().contains(|c| c == '\r' || c == '\n') {
+            Err("Invalid subject")
+        } else if self.content_type.as_deref().map_or(self.body.is_some(), |t| self.body.is_none() || t.is_empty() || t.contains(|c| c == '\r' || c == '\n')) {
+            Err("Invalid content type")
+        } else if self.webhook_url.as_deref().map_or(false, |url| !is_public_webhook_url(url)) {
+            Err("Invalid webhook url")
+        } else {
+            self.validate_attachments(profile)
+        }
+    }
+
+    /// Render the subject and body from the template, the subject given in request takes precedence.
+    fn apply_template(&mut self, template: &NotifyTemplate, locale: &Locale) -> std::result::Result<(), String> {
+        if self.body.is_some() || self.content_type.is_some() || self.html.is_some() || self.text.is_some() {
+            return Err("Body cannot be used with template".to_string());
+        }
+        if template.html.is_none() && template.text.is_none() {
+            return Err("Template has no content".to_string());
+        }
+
+        if self.subject.is_none() {
+            self.subject = Some(render_template(&template.subject, &self.variables, Escape::None, locale)?);
+        }
+        if let Some(html) = &template.html {
+            self.html = Some(render_template(html, &self.variables, Escape::Html, locale)?);
+        }
+        if let Some(text) = &template.text {
+            self.text = Some(render_template(text, &self.variables, Escape::None, locale)?);
+        }
+        // The content is in the language of the variant rather than the requested one.
+        self.locale = template.locale.clone();
+
+        Ok(())
+    }
+
+    fn validate_attachments(&self, profile: &NotifyProfile) -> std::result::Result<(), &'static str> {
+        let is_header_value = |s: &str| !s.is_empty() && !s.contains(|c| c == '\r' || c == '\n');
+        let mut total_size: i64 = 0;
+
+        for attachment in &self.attachments {
+            if !is_header_value(&attachment.filename) || !is_header_value(&attachment.content_type) {
+                return Err("Invalid attachment");
+            }
+            match (&attachment.disposition, &attachment.content_id) {
+                (_, Some(content_id)) if !is_header_value(content_id) || content_id.contains(|c| c == '<' || c == '>') => {
+                    return Err("Invalid attachment content id");
+                }
+                (AttachmentDisposition::Inline, None) => return Err("Missing content id of inline attachment"),
+                _ => (),
+            }
+            let content = openssl::base64::decode_block(&attachment.content)
+                .map_err(|_| "Invalid base64 attachment content")?;
+            total_size += content.len() as i64;
+        }
+
+        if total_size > profile.max_attachments_size.unwrap_or(DEFAULT_MAX_ATTACHMENTS_SIZE) {
+            Err("Attachments too large")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn render_template(source: &str, variables: &Map<String, Value>, escape: Escape, locale: &Locale) -> std::result::Result<String, String> {
+    Template::parse(source)
+        .and_then(|template| template.render(variables, escape, locale))
+        .map_err(|err| format!("Template error: {}", err))
+}
+
+/// Problems of a valid notification which may cause it to be displayed incorrectly.
+fn preview_warnings(request: &NotifyRequest, profile: &NotifyProfile) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if !request.subject.as_deref().unwrap_or_default().is_ascii() {
+        warnings.push("Subject contains non-ASCII characters without encoding".to_string());
+    }
+    if !profile.name.is_ascii() {
+        warnings.push("Sender name contains non-ASCII characters without encoding".to_string());
+    }
+
+    match (&request.content_type, &request.html, &request.text) {
+        (Some(content_type), _, _) if content_type.starts_with("text/html") => {
+            warnings.push("Missing text part, use html instead of body to generate one".to_string());
+        }
+        (None, Some(_), None) => warnings.push("Missing text part, generated from HTML".to_string()),
+        _ => (),
+    }
+
+    let contents = request.body.iter().chain(request.html.iter()).chain(request.text.iter());
+    for content in contents {
+        if !content.is_ascii() {
+            warnings.push("Body contains non-ASCII characters in 7bit transfer encoding".to_string());
+        }
+        if content.lines().any(|line| line.len() > MAX_LINE_LENGTH) {
+            warnings.push(format!("Body contains lines longer than {} characters", MAX_LINE_LENGTH));
+        }
+    }
+    warnings.dedup();
+
+    warnings
+}
+
+pub(super) fn is_valid_address(addr: &str) -> bool {
+    let forbidden = |c: char| c.is_whitespace() || c == '<' || c == '>' || c == ',';
+    match addr.rfind('@') {
+        Some(idx) => idx > 0 && idx + 1 < addr.len() && !addr.contains(forbidden),
+        None => false,
+    }
+}
+
+/// All the recipients of the notification are on the suppression list of its sender profile.
+fn is_all_suppressed(notify: &EmailNotify, suppressions: &[Suppression]) -> bool {
+    let recipients = notify.mail.recipients();
+    !recipients.is_empty() && recipients.iter().all(|addr| suppressions.iter().any(|s| {
+        s.sender_profile == notify.sender_profile && s.address == addr.to_lowercase()
+    }))
 }
 
 impl Into<MailData> for NotifyRequest {
     fn into(self) -> MailData {
         MailData {
             to: self.to,
-            subject: self.subject,
-            content_type: self.content_type,
-            body: self.body,
+            cc: self.cc,
+            bcc: self.bcc,
+            subject: self.subject.unwrap_or_default(),
+            content_type: self.content_type.unwrap_or_default(),
+            body: self.body.unwrap_or_default(),
+            text: self.text.or_else(|| self.html.as_deref().map(html_to_text)),
+            html: self.html,
+            attachments: self.attachments,
+            content_language: self.locale,
         }
     }
 }
@@ -33,47 +204,449 @@ enum NotifyStatusFilter {
     Pending,
     Sent,
     Error,
+    Suppressed,
+    Bounced,
+}
+
+impl NotifyStatusFilter {
+    fn kind(&self) -> Option<NotifyStatusKind> {
+        match self {
+            NotifyStatusFilter::All => None,
+            NotifyStatusFilter::Error => Some(NotifyStatusKind::Error),
+            NotifyStatusFilter::Pending => Some(NotifyStatusKind::Pending),
+            NotifyStatusFilter::Sent => Some(NotifyStatusKind::Sent),
+            NotifyStatusFilter::Suppressed => Some(NotifyStatusKind::Suppressed),
+            NotifyStatusFilter::Bounced => Some(NotifyStatusKind::Bounced),
+        }
+    }
+
+    fn matches_event(&self, event: &NotifyEvent) -> bool {
+        match self {
+            NotifyStatusFilter::All => true,
+            NotifyStatusFilter::Error => event.status == "Error",
+            NotifyStatusFilter::Pending => event.status == "Pending",
+            NotifyStatusFilter::Sent => event.status == "Sent",
+            NotifyStatusFilter::Suppressed => event.status == "Suppressed",
+            NotifyStatusFilter::Bounced => event.status == "Bounced",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder::Asc
+    }
 }
 
 #[derive(Deserialize)]
 struct ListNotifyQuery {
     filter: NotifyStatusFilter,
+    /// List the notifications of this sender profile only.
+    sender: Option<String>,
+    /// Max number of notifications in a page.
+    limit: Option<i64>,
+    /// `message_id` of the last notification in the previous page.
+    after: Option<String>,
+    /// RFC 3339 time range of when the notifications are queued.
+    since: Option<String>,
+    until: Option<String>,
+    recipient: Option<String>,
+    subject: Option<String>,
+    /// Oldest first by default.
+    #[serde(default)]
+    order: SortOrder,
 }
 
-#[derive(Serialize)]
+impl ListNotifyQuery {
+    fn into_query(self) -> std::result::Result<NotifyQuery, &'static str> {
+        let limit = self.limit.unwrap_or(LIST_DEFAULT_LIMIT);
+        if limit <= 0 || limit > LIST_MAX_LIMIT {
+            return Err("Invalid limit");
+        }
+        self.into_query_with_limit(limit)
+    }
+
+    /// All the matched notifications are exported unless `limit` is specified.
+    fn into_export_query(self) -> std::result::Result<NotifyQuery, &'static str> {
+        let limit = self.limit.unwrap_or(0);
+        if limit < 0 {
+            return Err("Invalid limit");
+        }
+        self.into_query_with_limit(limit)
+    }
+
+    fn into_query_with_limit(self, limit: i64) -> std::result::Result<NotifyQuery, &'static str> {
+        let after = match &self.after {
+            Some(after) => Some(ObjectId::with_string(after).map_err(|_| "Invalid cursor")?),
+            None => None,
+        };
+
+        Ok(NotifyQuery {
+            status: self.filter.kind(),
+            after,
+            since: parse_time(self.since.as_deref())?,
+            until: parse_time(self.until.as_deref())?,
+            recipient: self.recipient.filter(|recipient| !recipient.is_empty()),
+            subject: self.subject.filter(|subject| !subject.is_empty()),
+            descending: match self.order {
+                SortOrder::Asc => false,
+                SortOrder::Desc => true,
+            },
+            limit,
+        })
+    }
+}
+
+fn parse_time(time: Option<&str>) -> std::result::Result<Option<SystemTime>, &'static str> {
+    match time {
+        Some(time) => chrono::DateTime::parse_from_rfc3339(time)
+            .map(|time| Some(time.into()))
+            .map_err(|_| "Invalid time range"),
+        None => Ok(None),
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+/// Taken along with the `ListNotifyQuery` from the same query string.
+#[derive(Deserialize)]
+struct ExportNotifyQuery {
+    format: ExportFormat,
+    #[serde(default)]
+    exclude_body: bool,
+}
+
+#[derive(Deserialize)]
+struct StreamNotifyQuery {
+    /// Stream the notifications of this user instead of the caller.
+    uid: Option<String>,
+    filter: Option<NotifyStatusFilter>,
+    /// Stream the notifications of this sender profile only.
+    sender: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
 enum NotifyStatus {
     Pending,
     Sent,
     Error,
+    Suppressed,
+    Bounced,
+}
+
+#[derive(Serialize, Clone)]
+struct PubRecipientInfo {
+    address: String,
+    status: NotifyStatus,
+    error: Option<String>,
 }
 
+/// A page of the notification list.
 #[derive(Serialize)]
+struct PubNotifyPage {
+    items: Vec<PubNotifyInfo>,
+    /// `after` of the next page, `None` if this is the last page.
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
 struct PubNotifyInfo {
     message_id: String,
     status: NotifyStatus,
     error: Option<String>,
+    recipients: Vec<PubRecipientInfo>,
+    /// Address of the relay which accepted the notification.
+    relay: Option<String>,
+    /// RFC 3339 time before which a notification held by the rate limit is not sent.
+    next_attempt_at: Option<String>,
+    /// RFC 3339 time when the notification is queued.
+    created_at: String,
+    first_attempt_at: Option<String>,
+    sent_at: Option<String>,
+    attempts: i32,
+    /// The final SMTP reply of the last attempt.
+    reply_code: Option<i32>,
+    reply_text: Option<String>,
+    /// The opens and clicks, `null` if they are not tracked.
+    tracking: Option<PubTracking>,
 }
 
-impl From<EmailNotify> for PubNotifyInfo {
-    fn from(inner_notify: EmailNotify) -> Self {
-        let mut notify = PubNotifyInfo {
-            message_id: hex::encode(inner_notify._id.bytes()),
-            status: NotifyStatus::Pending,
-            error: None,
+#[derive(Serialize, Clone)]
+struct PubTracking {
+    opens: i32,
+    clicks: i32,
+    first_opened_at: Option<String>,
+    first_clicked_at: Option<String>,
+    clicked_links: Vec<String>,
+}
+
+/// Number of the pending notifications of each priority.
+#[derive(Serialize, Default)]
+struct QueueDepth {
+    high: i64,
+    normal: i64,
+    bulk: i64,
+}
+
+#[derive(Serialize)]
+struct StatusCounts {
+    pending: i64,
+    sent: i64,
+    error: i64,
+    suppressed: i64,
+    bounced: i64,
+}
+
+#[derive(Serialize)]
+struct ErrorCount {
+    error: String,
+    count: i64,
+}
+
+#[derive(Serialize)]
+struct DomainCount {
+    domain: String,
+    count: i64,
+}
+
+#[derive(Serialize)]
+struct TrackingStats {
+    tracked: i64,
+    opened: i64,
+    clicked: i64,
+    /// Ratio of the opened notifications to the tracked ones, `null` if there is none.
+    open_rate: Option<f64>,
+    click_rate: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct NotifyStats {
+    /// Current depth of the queue, regardless of the time window.
+    pending: QueueDepth,
+    /// The rest are of the notifications queued in the time window.
+    counts: StatusCounts,
+    /// Ratio of the sent notifications to the sent and failed ones, `null` if there is none.
+    success_rate: Option<f64>,
+    mean_time_to_send_ms: Option<f64>,
+    p95_time_to_send_ms: Option<i64>,
+    top_errors: Vec<ErrorCount>,
+    top_domains: Vec<DomainCount>,
+    tracking: TrackingStats,
+}
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    /// Time window of the notifications queued, in RFC 3339.
+    since: Option<String>,
+    until: Option<String>,
+    /// Stats of this sender profile only.
+    sender: Option<String>,
+}
+
+#[derive(Serialize)]
+struct MailPreview {
+    message: String,
+    warnings: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum BatchQueueResult {
+    Queued(PubNotifyInfo),
+    Rejected {
+        error: String,
+    },
+}
+
+/// A notification in the export, flat enough to be a row of CSV.
+#[derive(Serialize)]
+struct ExportedNotify {
+    message_id: String,
+    status: NotifyStatus,
+    error: Option<String>,
+    created_at: String,
+    first_attempt_at: Option<String>,
+    sent_at: Option<String>,
+    attempts: i32,
+    relay: Option<String>,
+    reply_code: Option<i32>,
+    reply_text: Option<String>,
+    to: Vec<String>,
+    cc: Vec<String>,
+    bcc: Vec<String>,
+    subject: String,
+    /// Excluded from the export on request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    html: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+}
+
+const EXPORT_CSV_COLUMNS: &[&str] = &[
+    "message_id", "status", "error", "created_at", "first_attempt_at", "sent_at", "attempts",
+    "relay", "reply_code", "reply_text", "to", "cc", "bcc", "subject",
+];
+const EXPORT_CSV_BODY_COLUMNS: &[&str] = &["body", "html", "text"];
+
+impl ExportedNotify {
+    fn new(notify: EmailNotify, include_body: bool) -> Self {
+        let created_at = created_at(&notify);
+        let (status, error) = pub_status(&notify.status);
+        let mail = notify.mail;
+        let (body, html, text) = if include_body {
+            (Some(mail.body), Some(mail.html.unwrap_or_default()), Some(mail.text.unwrap_or_default()))
+        } else {
+            (None, None, None)
         };
-        match inner_notify.status {
-            NotifyState::Pending => {
-                notify.status = NotifyStatus::Pending;
-            }
-            NotifyState::Sent => {
-                notify.status = NotifyStatus::Sent;
-            }
-            NotifyState::Error(pub_err, _) => {
-                notify.status = NotifyStatus::Error;
-                notify.error = Some(pub_err);
-            }
+        ExportedNotify {
+            message_id: hex::encode(notify._id.bytes()),
+            status,
+            error,
+            created_at,
+            first_attempt_at: notify.first_attempt_at.map(|time| time.to_rfc3339()),
+            sent_at: notify.sent_at.map(|time| time.to_rfc3339()),
+            attempts: notify.attempts,
+            relay: notify.relay,
+            reply_code: notify.reply_code,
+            reply_text: notify.reply_text,
+            to: mail.to,
+            cc: mail.cc,
+            bcc: mail.bcc,
+            subject: mail.subject,
+            body,
+            html,
+            text,
         }
-        notify
+    }
+
+    fn csv_header(include_body: bool) -> String {
+        let mut columns = EXPORT_CSV_COLUMNS.to_vec();
+        if include_body {
+            columns.extend_from_slice(EXPORT_CSV_BODY_COLUMNS);
+        }
+        columns.join(",") + "\r\n"
+    }
+
+    /// Addresses are separated by `;` in a field, the body columns are present only if the body is included.
+    fn csv_row(&self) -> String {
+        let status = match self.status {
+            NotifyStatus::Pending => "Pending",
+            NotifyStatus::Sent => "Sent",
+            NotifyStatus::Error => "Error",
+            NotifyStatus::Suppressed => "Suppressed",
+            NotifyStatus::Bounced => "Bounced",
+        };
+        let mut fields = vec![
+            self.message_id.clone(),
+            status.to_string(),
+            self.error.clone().unwrap_or_default(),
+            self.created_at.clone(),
+            self.first_attempt_at.clone().unwrap_or_default(),
+            self.sent_at.clone().unwrap_or_default(),
+            self.attempts.to_string(),
+            self.relay.clone().unwrap_or_default(),
+            self.reply_code.map(|code| code.to_string()).unwrap_or_default(),
+            self.reply_text.clone().unwrap_or_default(),
+            self.to.join(";"),
+            self.cc.join(";"),
+            self.bcc.join(";"),
+            self.subject.clone(),
+        ];
+        if let (Some(body), Some(html), Some(text)) = (&self.body, &self.html, &self.text) {
+            fields.extend_from_slice(&[body.clone(), html.clone(), text.clone()]);
+        }
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        fields.join(",") + "\r\n"
+    }
+}
+
+/// Quote the field as RFC 4180 if it contains a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\r' || c == '\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn pub_status(state: &NotifyState) -> (NotifyStatus, Option<String>) {
+    match state {
+        NotifyState::Pending => (NotifyStatus::Pending, None),
+        NotifyState::Sent => (NotifyStatus::Sent, None),
+        NotifyState::Error(pub_err, _) => (NotifyStatus::Error, Some(pub_err.clone())),
+        NotifyState::Suppressed => (NotifyStatus::Suppressed, None),
+        NotifyState::Bounced(detail) => (NotifyStatus::Bounced, Some(detail.clone())),
+    }
+}
+
+impl From<&EmailNotify> for PubNotifyInfo {
+    fn from(inner_notify: &EmailNotify) -> Self {
+        let (status, error) = pub_status(&inner_notify.status);
+        let tracking = if inner_notify.tracked {
+            Some(PubTracking {
+                opens: inner_notify.opens,
+                clicks: inner_notify.clicks,
+                first_opened_at: inner_notify.first_opened_at.map(|time| time.to_rfc3339()),
+                first_clicked_at: inner_notify.first_clicked_at.map(|time| time.to_rfc3339()),
+                clicked_links: inner_notify.clicked_links.clone(),
+            })
+        } else {
+            None
+        };
+        let recipients = inner_notify.recipients
+            .iter()
+            .map(|recipient| {
+                let (status, error) = pub_status(&recipient.status);
+                PubRecipientInfo {
+                    address: recipient.address.clone(),
+                    status,
+                    error,
+                }
+            })
+            .collect();
+
+        PubNotifyInfo {
+            message_id: hex::encode(inner_notify._id.bytes()),
+            status,
+            error,
+            recipients,
+            relay: inner_notify.relay.clone(),
+            next_attempt_at: inner_notify.next_attempt_at.map(|time| time.to_rfc3339()),
+            created_at: created_at(inner_notify),
+            first_attempt_at: inner_notify.first_attempt_at.map(|time| time.to_rfc3339()),
+            sent_at: inner_notify.sent_at.map(|time| time.to_rfc3339()),
+            attempts: inner_notify.attempts,
+            reply_code: inner_notify.reply_code,
+            reply_text: inner_notify.reply_text.clone(),
+            tracking,
+        }
+    }
+}
+
+impl From<EmailNotify> for PubNotifyInfo {
+    fn from(inner_notify: EmailNotify) -> Self {
+        PubNotifyInfo::from(&inner_notify)
+    }
+}
+
+fn created_at(notify: &EmailNotify) -> String {
+    match &notify.created_at {
+        Some(time) => time.to_rfc3339(),
+        None => notify._id.timestamp().to_rfc3339(),
     }
 }
 
@@ -85,45 +658,309 @@ fn handel_model_error(err: model::Error) -> actix_web::Error {
     
 }
 
+/// The notification of a repeated idempotency key is not found, when it's still being queued by a concurrent request.
+fn handel_idempotency_error(err: model::Error) -> actix_web::Error {
+    match err {
+        model::Error::NoRecord => web_errors::ErrorConflict("The idempotency key is being used by a concurrent request"),
+        err => web_errors::ErrorInternalServerError(err)
+    }
+}
+
 type Auth = ExtensionMove<UserProfile>;
-type ServiceProfile = ExtensionMove<NotifyProfile>;
 type Model = Data<model::Model>;
 type EmailNotifyService = Data<crate::service::EmailNotifyService>;
 
 const ERR_ACCESS_DENIED: &str = "Access denied";
+const ERR_SENDER_NOT_FOUND: &str = "Sender profile not found";
+
+const HEADER_IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+const IDEMPOTENCY_KEY_MAX_LEN: usize = 255;
+const BATCH_MAX_SIZE: usize = 1000;
+const RECIPIENTS_MAX_COUNT: usize = 100;
+const DEFAULT_MAX_ATTACHMENTS_SIZE: i64 = 10 * 1024 * 1024;
+/// https://tools.ietf.org/html/rfc5322#section-2.1.1
+const MAX_LINE_LENGTH: usize = 998;
+/// Large enough for requests with base64 encoded attachments.
+const JSON_PAYLOAD_LIMIT: usize = 64 * 1024 * 1024;
+const LIST_DEFAULT_LIMIT: i64 = 100;
+const LIST_MAX_LIMIT: i64 = 1000;
+/// Interval of sending a comment line to keep the idle status stream alive through proxies.
+const STREAM_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+fn idempotency_key(request: &HttpRequest, notify_request: &mut NotifyRequest) -> Result<Option<String>> {
+    let key = match request.headers().get(HEADER_IDEMPOTENCY_KEY) {
+        Some(value) => Some(value
+            .to_str()
+            .map_err(|_| web_errors::ErrorBadRequest("Invalid idempotency key"))?
+            .to_string()),
+        None => notify_request.idempotency_key.take(),
+    };
+
+    match key {
+        Some(key) if key.is_empty() || key.len() > IDEMPOTENCY_KEY_MAX_LEN => {
+            Err(web_errors::ErrorBadRequest("Invalid idempotency key"))
+        }
+        key => Ok(key),
+    }
+}
+
+type TemplateCache = HashMap<(ObjectId, String, Option<String>, Option<i32>), NotifyTemplate>;
+
+/// The `EmailNotify` service record of the user with the sender name,
+/// or the default one if `sender` is `None`.
+///
+/// The default one is the record marked as default, or the first record if none is marked.
+fn find_sender_profile<'a>(auth: &'a UserProfile, sender: Option<&str>) -> Option<(&'a ObjectId, &'a NotifyProfile)> {
+    let mut profiles = auth.services
+        .iter()
+        .filter_map(|s| NotifyProfile::extract_from(&s.service).map(|profile| (&s._id, profile)));
+    match sender {
+        Some(sender) => profiles.find(|(_, profile)| profile.sender.as_deref() == Some(sender)),
+        None => {
+            let profiles: Vec<_> = profiles.collect();
+            profiles.iter()
+                .find(|(_, profile)| profile.default)
+                .or(profiles.first())
+                .cloned()
+        }
+    }
+}
+
+fn sender_profile(auth: &UserProfile, sender: Option<&str>) -> Result<(ObjectId, NotifyProfile)> {
+    match find_sender_profile(auth, sender) {
+        Some((service_id, profile)) => Ok((service_id.clone(), profile.clone())),
+        None if sender.is_some() => Err(web_errors::ErrorBadRequest(ERR_SENDER_NOT_FOUND)),
+        None => Err(web_errors::ErrorForbidden(ERR_ACCESS_DENIED)),
+    }
+}
+
+/// Id of the `EmailNotify` service record of the user chosen by `sender`.
+pub(super) fn notify_service_id(auth: &UserProfile, sender: Option<&str>) -> Result<ObjectId> {
+    sender_profile(auth, sender).map(|(service_id, _)| service_id)
+}
+
+/// Render the template referenced by the request if any, templates are cached in `templates`
+/// by sender profile, name, locale and version to be shared among requests of a batch.
+/// The inner error is the reason to reject the request.
+async fn render_request(
+    request: &mut NotifyRequest,
+    service_id: &ObjectId,
+    model: &model::Model,
+    templates: &mut TemplateCache,
+) -> Result<std::result::Result<Option<TemplateRef>, String>> {
+    let locale = match request.locale.as_deref().map(Locale::parse) {
+        Some(None) => return Ok(Err("Invalid locale".to_string())),
+        Some(Some(locale)) => {
+            request.locale = Some(locale.tag().to_string());
+            Some(locale)
+        }
+        None => None,
+    };
+    let key = match request.template.take() {
+        Some(name) => (service_id.clone(), name, request.locale.clone(), request.template_version),
+        None => return Ok(Ok(None)),
+    };
+    if !templates.contains_key(&key) {
+        let fallbacks = locale.as_ref().map(|locale| locale.fallbacks()).unwrap_or_default();
+        match model.get_localized_template(service_id, &key.1, &fallbacks, key.3).await {
+            Ok(template) => {
+                templates.insert(key.clone(), template);
+            }
+            Err(model::Error::NoRecord) => return Ok(Err("Template not found".to_string())),
+            Err(err) => return Err(web_errors::ErrorInternalServerError(err)),
+        }
+    }
+
+    let template = &templates[&key];
+    Ok(request.apply_template(template, &locale.unwrap_or_default()).map(|_| Some(TemplateRef {
+        name: template.name.clone(),
+        locale: template.locale.clone(),
+        version: template.version,
+    })))
+}
 
 #[post("/queue")]
 async fn queue(
-    service: ServiceProfile,
     auth: Auth,
-    Json(request): Json<NotifyRequest>,
+    Json(mut request): Json<NotifyRequest>,
+    http_request: HttpRequest,
     model: Model,
     push_service: EmailNotifyService,
 ) -> Result<Json<PubNotifyInfo>> {
-
-    let record = auth
-        .services
-        .iter()
-        .find(|s| NotifyProfile::extract_from(&s.service).is_some());
+    let idempotency_key = idempotency_key(&http_request, &mut request)?;
+    let (service_id, service) = sender_profile(&auth, request.sender.as_deref())?;
 
     log::debug!("Received request.");
 
-    if let Some(record) = record {
-        let service_id = record._id.clone();
+    let template = render_request(&mut request, &service_id, &model, &mut HashMap::new())
+        .await?
+        .map_err(web_errors::ErrorBadRequest)?;
+    request.validate(&service).map_err(web_errors::ErrorBadRequest)?;
+
+    let priority = request.priority.unwrap_or_default();
+    let webhook_url = request.webhook_url.take();
+    let mut notify =
+        model.new_email_notify(service_id, request.into(), service.email_address.as_str());
+    notify.idempotency_key = idempotency_key;
+    notify.template = template;
+    notify.set_priority(priority);
+    notify.webhook_url = webhook_url;
 
-        let notify =
-            model.new_email_notify(service_id, request.into(), service.email_address.as_str());
-        model
-            .add_notification(&notify)
+    let suppressions = model.find_suppressions(&[notify.sender_profile.clone()], &notify.mail.recipients())
+        .await
+        .map_err(handel_model_error)?;
+    if is_all_suppressed(&notify, &suppressions) {
+        log::debug!("All recipients are suppressed.");
+        notify.set_suppressed();
+    }
+
+    let previous = model
+        .add_notification_idempotent(&notify)
+        .await
+        .map_err(handel_idempotency_error)?;
+    if let Some(previous) = previous {
+        log::debug!("Repeated idempotency key, return the previous notification.");
+        return Ok(Json(PubNotifyInfo::from(previous)));
+    }
+
+    push_service.enqueue().map_err(|err| web_errors::ErrorInternalServerError(err))?;
+
+    Ok(Json(PubNotifyInfo::from(notify)))
+}
+
+#[post("/queue/batch")]
+async fn queue_batch(
+    auth: Auth,
+    Json(requests): Json<Vec<NotifyRequest>>,
+    model: Model,
+    push_service: EmailNotifyService,
+) -> Result<Json<Vec<BatchQueueResult>>> {
+    if requests.len() > BATCH_MAX_SIZE {
+        return Err(web_errors::ErrorBadRequest("Too many notifications in a batch"));
+    }
+
+    let service_ids: Vec<ObjectId> = auth.services
+        .iter()
+        .filter(|s| NotifyProfile::extract_from(&s.service).is_some())
+        .map(|s| s._id.clone())
+        .collect();
+
+    let keys: Vec<String> = requests
+        .iter()
+        .filter_map(|r| r.idempotency_key.clone())
+        .collect();
+    let addresses: Vec<&str> = requests
+        .iter()
+        .flat_map(|r| r.to.iter().chain(r.cc.iter()).chain(r.bcc.iter()))
+        .map(|addr| addr.as_str())
+        .collect();
+    let suppressions = model
+        .find_suppressions(&service_ids, &addresses)
+        .await
+        .map_err(handel_model_error)?;
+    // Idempotency keys are scoped by sender profile.
+    let mut queued_keys: HashMap<(ObjectId, String), PubNotifyInfo> = HashMap::new();
+    if keys.len() > 0 {
+        let previous = model
+            .get_notifications_by_idempotency_keys(&service_ids, &keys)
             .await
             .map_err(handel_model_error)?;
+        for notify in previous {
+            if let Some(key) = notify.idempotency_key.clone() {
+                queued_keys.insert((notify.sender_profile.clone(), key), PubNotifyInfo::from(notify));
+            }
+        }
+    }
 
-        push_service.enqueue().map_err(|err| web_errors::ErrorInternalServerError(err))?;
+    let mut results = Vec::with_capacity(requests.len());
+    let mut notifications = Vec::with_capacity(requests.len());
+    // Index in `results` of each notification to insert.
+    let mut result_indices = Vec::with_capacity(requests.len());
+    let mut templates = HashMap::new();
+    for mut request in requests {
+        let key = request.idempotency_key.take();
+        let (service_id, service) = match find_sender_profile(&auth, request.sender.as_deref()) {
+            Some((service_id, service)) => (service_id, service),
+            None => {
+                results.push(BatchQueueResult::Rejected { error: ERR_SENDER_NOT_FOUND.to_string() });
+                continue;
+            }
+        };
+        let template = match render_request(&mut request, service_id, &model, &mut templates).await? {
+            Ok(template) => template,
+            Err(error) => {
+                results.push(BatchQueueResult::Rejected { error });
+                continue;
+            }
+        };
+        let validation = match &key {
+            Some(key) if key.is_empty() || key.len() > IDEMPOTENCY_KEY_MAX_LEN => Err("Invalid idempotency key"),
+            _ => request.validate(service),
+        };
+        if let Err(err) = validation {
+            results.push(BatchQueueResult::Rejected { error: err.to_string() });
+            continue;
+        }
+        if let Some(info) = key.as_ref().and_then(|key| queued_keys.get(&(service_id.clone(), key.clone()))) {
+            results.push(BatchQueueResult::Queued(info.clone()));
+            continue;
+        }
 
-        Ok(Json(PubNotifyInfo::from(notify)))
-    } else {
-        Err(web_errors::ErrorForbidden(ERR_ACCESS_DENIED))
+        let priority = request.priority.unwrap_or_default();
+        let webhook_url = request.webhook_url.take();
+        let mut notify = model.new_email_notify(service_id.clone(), request.into(), service.email_address.as_str());
+        notify.idempotency_key = key;
+        notify.template = template;
+        notify.set_priority(priority);
+        notify.webhook_url = webhook_url;
+        if is_all_suppressed(&notify, &suppressions) {
+            notify.set_suppressed();
+        }
+        let info = PubNotifyInfo::from(&notify);
+        if let Some(key) = &notify.idempotency_key {
+            queued_keys.insert((service_id.clone(), key.clone()), info.clone());
+        }
+        result_indices.push(results.len());
+        results.push(BatchQueueResult::Queued(info));
+        notifications.push(notify);
     }
+
+    if notifications.len() > 0 {
+        let repeated = model
+            .add_notifications(&notifications)
+            .await
+            .map_err(handel_idempotency_error)?;
+        // Queued by a concurrent request with the same idempotency keys.
+        for (idx, previous) in repeated {
+            results[result_indices[idx]] = BatchQueueResult::Queued(PubNotifyInfo::from(previous));
+        }
+
+        push_service.enqueue().map_err(|err| web_errors::ErrorInternalServerError(err))?;
+    }
+
+    Ok(Json(results))
+}
+
+#[post("/preview")]
+async fn preview(
+    auth: Auth,
+    Json(mut request): Json<NotifyRequest>,
+    model: Model,
+    push_service: EmailNotifyService,
+) -> Result<Json<MailPreview>> {
+    let (service_id, service) = sender_profile(&auth, request.sender.as_deref())?;
+
+    render_request(&mut request, &service_id, &model, &mut HashMap::new())
+        .await?
+        .map_err(web_errors::ErrorBadRequest)?;
+    request.validate(&service).map_err(web_errors::ErrorBadRequest)?;
+    let warnings = preview_warnings(&request, &service);
+
+    let notify = model.new_email_notify(service_id, request.into(), service.email_address.as_str());
+    let message = push_service.preview(&notify, &service)
+        .map_err(web_errors::ErrorBadRequest)?;
+
+    Ok(Json(MailPreview { message, warnings }))
 }
 
 #[get("/{message_id}")]
@@ -148,35 +985,196 @@ async fn list_notifications(
     auth: Auth, 
     model: Model, 
     Query(params): Query<ListNotifyQuery>,
-) -> Result<Json<Vec<PubNotifyInfo>>> {
+) -> Result<Json<PubNotifyPage>> {
     let profile: UserProfile = model.allow_self_or_admin_access(&auth, auth.access, &uid).await?;
+    let service_ids = listed_service_ids(&profile, params.sender.as_deref())?;
+    let query = params.into_query().map_err(web_errors::ErrorBadRequest)?;
 
-    let service_profile = profile.services.iter().find(|s| match s.service {
-        Service::EmailNotify(_) => true,
-        _ => false,
-    }).ok_or(web_errors::ErrorNotFound("Service not found"))?;
-
-    let filter = match params.filter {
-        NotifyStatusFilter::All => |_: &EmailNotify| true,
-        NotifyStatusFilter::Error => |t: &EmailNotify| t.status.is_error(),
-        NotifyStatusFilter::Pending => |t: &EmailNotify| t.status.is_pending(),
-        NotifyStatusFilter::Sent => |t: &EmailNotify| t.status.is_sent(),
-    };
-
-    let result = model.get_all_notifications_by_service(&service_profile._id)
+    let result = model.find_notifications(&service_ids, &query)
         .await
         .map_err(handel_model_error)?;
     
-    let result: Vec<PubNotifyInfo> = result.into_iter()
-        .filter(filter)
+    let items: Vec<PubNotifyInfo> = result.into_iter()
         .map(PubNotifyInfo::from)
         .collect();
+    // A full page may be followed by more notifications.
+    let next_cursor = match items.last() {
+        Some(last) if items.len() as i64 == query.limit => Some(last.message_id.clone()),
+        _ => None,
+    };
+
+    Ok(Json(PubNotifyPage { items, next_cursor }))
+}
+
+/// Stream the notifications matched by the same filters as the list, as CSV or NDJSON.
+#[get("/export/{uid}")]
+async fn export_notifications(
+    Path(uid): Path<String>,
+    auth: Auth,
+    model: Model,
+    Query(params): Query<ListNotifyQuery>,
+    Query(export): Query<ExportNotifyQuery>,
+) -> Result<HttpResponse> {
+    let profile: UserProfile = model.allow_self_or_admin_access(&auth, auth.access, &uid).await?;
+    let service_ids = listed_service_ids(&profile, params.sender.as_deref())?;
+    let query = params.into_export_query().map_err(web_errors::ErrorBadRequest)?;
+
+    let notifications = model.find_notifications_stream(&service_ids, &query)
+        .await
+        .map_err(handel_model_error)?;
+
+    let format = export.format;
+    let include_body = !export.exclude_body;
+    let header = match format {
+        ExportFormat::Csv => Some(Bytes::from(ExportedNotify::csv_header(include_body))),
+        ExportFormat::Ndjson => None,
+    };
+    let rows = notifications.map(move |notify| {
+        let notify = ExportedNotify::new(notify, include_body);
+        Bytes::from(match format {
+            ExportFormat::Csv => notify.csv_row(),
+            ExportFormat::Ndjson => serde_json::to_string(&notify).unwrap() + "\n",
+        })
+    });
+    let (content_type, filename) = match format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "notifications.csv"),
+        ExportFormat::Ndjson => ("application/x-ndjson", "notifications.ndjson"),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .header("Content-Disposition", format!("attachment; filename=\"{}\"", filename))
+        .streaming(Box::pin(stream::iter(header).chain(rows).map(Ok::<_, actix_web::Error>))))
+}
+
+/// The sender profiles of the user to list the notifications of, 404 if there is none.
+fn listed_service_ids(profile: &UserProfile, sender: Option<&str>) -> Result<Vec<ObjectId>> {
+    let service_ids: Vec<ObjectId> = match sender {
+        Some(_) => find_sender_profile(profile, sender)
+            .map(|(service_id, _)| service_id.clone())
+            .into_iter()
+            .collect(),
+        None => profile.services.iter().filter(|s| match s.service {
+            Service::EmailNotify(_) => true,
+            _ => false,
+        }).map(|s| s._id.clone()).collect(),
+    };
+    if service_ids.is_empty() {
+        Err(web_errors::ErrorNotFound("Service not found"))
+    } else {
+        Ok(service_ids)
+    }
+}
+
+/// Push the status events of the notifications of a user as Server-Sent Events.
+#[get("/stream")]
+async fn stream_notifications(
+    auth: Auth,
+    model: Model,
+    push_service: EmailNotifyService,
+    Query(params): Query<StreamNotifyQuery>,
+) -> Result<HttpResponse> {
+    let uid = params.uid.clone().unwrap_or_else(|| auth.uid.clone());
+    let profile: UserProfile = model.allow_self_or_admin_access(&auth, auth.access, &uid).await?;
+    let service_ids = listed_service_ids(&profile, params.sender.as_deref())?;
+    let filter = params.filter.unwrap_or(NotifyStatusFilter::All);
+
+    let events = push_service.subscribe().filter_map(move |event| future::ready(match event {
+        Ok(event) if service_ids.contains(&event.sender_profile) && filter.matches_event(&event) => {
+            let data = serde_json::to_string(&event).unwrap();
+            Some(Bytes::from(format!("event: status\ndata: {}\n\n", data)))
+        }
+        Ok(_) => None,
+        Err(err) => {
+            log::warn!("Notification status stream lagged behind: {:?}", err);
+            None
+        }
+    }));
+    let keep_alive = tokio::time::interval(STREAM_KEEP_ALIVE_INTERVAL)
+        .map(|_| Bytes::from_static(b": keep-alive\n\n"));
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(stream::select(events, keep_alive).map(Ok::<_, actix_web::Error>)))
+}
+
+#[get("/stats/{uid}")]
+async fn notify_stats(
+    Path(uid): Path<String>,
+    auth: Auth,
+    model: Model,
+    Query(params): Query<StatsQuery>,
+) -> Result<Json<NotifyStats>> {
+    let profile: UserProfile = model.allow_self_or_admin_access(&auth, auth.access, &uid).await?;
+    let service_ids = listed_service_ids(&profile, params.sender.as_deref())?;
+    let since = parse_time(params.since.as_deref()).map_err(web_errors::ErrorBadRequest)?;
+    let until = parse_time(params.until.as_deref()).map_err(web_errors::ErrorBadRequest)?;
+
+    let counts = model.count_pending_notifications(&service_ids)
+        .await
+        .map_err(handel_model_error)?;
+    let mut pending = QueueDepth::default();
+    for (priority, count) in counts {
+        match priority {
+            NotifyPriority::High => pending.high += count,
+            NotifyPriority::Normal => pending.normal += count,
+            NotifyPriority::Bulk => pending.bulk += count,
+        }
+    }
+
+    let stats = model.notification_statistics(&service_ids, since, until)
+        .await
+        .map_err(handel_model_error)?;
+    let attempted = stats.sent + stats.error + stats.bounced;
+    let success_rate = if attempted > 0 {
+        Some(stats.sent as f64 / attempted as f64)
+    } else {
+        None
+    };
+    let tracking_rate = |count: i64| if stats.tracked > 0 {
+        Some(count as f64 / stats.tracked as f64)
+    } else {
+        None
+    };
+    let tracking = TrackingStats {
+        tracked: stats.tracked,
+        opened: stats.opened,
+        clicked: stats.clicked,
+        open_rate: tracking_rate(stats.opened),
+        click_rate: tracking_rate(stats.clicked),
+    };
 
-    Ok(Json(result))
+    Ok(Json(NotifyStats {
+        pending,
+        counts: StatusCounts {
+            pending: stats.pending,
+            sent: stats.sent,
+            error: stats.error,
+            suppressed: stats.suppressed,
+            bounced: stats.bounced,
+        },
+        success_rate,
+        mean_time_to_send_ms: stats.mean_time_to_send,
+        p95_time_to_send_ms: stats.p95_time_to_send,
+        top_errors: stats.top_errors.into_iter()
+            .map(|(error, count)| ErrorCount { error, count })
+            .collect(),
+        top_domains: stats.top_domains.into_iter()
+            .map(|(domain, count)| DomainCount { domain, count })
+            .collect(),
+        tracking,
+    }))
 }
 
 pub fn config(cfg: &mut ServiceConfig) {
-    cfg.service(queue)
+    cfg.app_data(JsonConfig::default().limit(JSON_PAYLOAD_LIMIT))
+        .service(queue)
+        .service(queue_batch)
+        .service(preview)
+        .service(stream_notifications)
         .service(query_status)
-        .service(list_notifications);
+        .service(list_notifications)
+        .service(export_notifications)
+        .service(notify_stats);
 }
@@ -5,15 +5,16 @@ use actix_web::{
     web::{self, Path},
     HttpRequest, HttpResponse, Responder, Result,
 };
-use model::{ Service, ServiceManagerProfile, UserProfile, ValidateProfile};
+use model::{ Service, ServiceManagerProfile, ServiceRecord, UserProfile, ValidateProfile};
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 use web::Json;
 
-use crate::{model, model::Error as ModelError, utils::variant_eq};
+use crate::{model, model::{Error as ModelError, days_ago}, service::{ConnectionReport, EmailNotifyService}, utils::variant_eq};
 
 use super::access_check::AccessCheckUtils;
 use super::extractor::ExtensionMove;
+use super::notify::is_valid_address;
 
 #[derive(Serialize, Deserialize)]
 struct ServiceProfileData {
@@ -30,11 +31,58 @@ impl From<model::ServiceRecord> for ServiceProfileData {
     }
 }
 
+#[derive(Deserialize)]
+struct ConnectionTestRequest {
+    /// Send a test mail to this address if provided.
+    to: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PurgeRequest {
+    /// Purge the notifications queued more than this number of days ago.
+    older_than_days: i32,
+    /// Only strip the body and attachments, keep the rest of the notifications.
+    #[serde(default)]
+    body_only: bool,
+}
+
+#[derive(Serialize)]
+struct PurgeResult {
+    stripped: i64,
+    deleted: i64,
+}
+
 type Model = web::Data<model::Model>;
 type ServiceProfile = ExtensionMove<ServiceManagerProfile>;
 type Auth = ExtensionMove<UserProfile>;
 
 const ERR_ACCESS_DENIED: &str = "Access denied";
+const ERR_DEFAULT_SENDER_EXISTED: &str = "Another sender profile is already the default one";
+
+/// Reject the settings of a service profile which cannot be applied as given.
+fn validate_service(data: &Service) -> std::result::Result<(), &'static str> {
+    if let Service::EmailNotify(profile) = data {
+        if profile.rate_limit.as_ref().map_or(false, |limit| limit.max_messages < 1 || limit.interval < 1) {
+            return Err("Invalid rate limit");
+        }
+        if profile.retention.map_or(false, |retention| !retention.is_valid()) {
+            return Err("Invalid retention");
+        }
+    }
+    Ok(())
+}
+
+/// Another `EmailNotify` service of the user, except `service_id`, is already the default one.
+fn conflicts_with_default(services: &[ServiceRecord], data: &Service, service_id: Option<&ObjectId>) -> bool {
+    let is_default = |service: &Service| match service {
+        Service::EmailNotify(profile) => profile.default,
+        _ => false,
+    };
+    is_default(data)
+        && services
+            .iter()
+            .any(|s| Some(&s._id) != service_id && is_default(&s.service))
+}
 
 fn handle_model_err(err: ModelError) -> actix_web::Error {
     match err {
@@ -72,8 +120,8 @@ async fn add_service(
 
     if profile
         .services
-        .into_iter()
-        .any(|s| variant_eq(&s.service, &data))
+        .iter()
+        .any(|s| s.service.conflicts_with(&data))
     {
         return Err(web_errors::ErrorConflict("Service already existed"));
     }
@@ -81,6 +129,10 @@ async fn add_service(
     if !data.validate_properties(&service) {
         return Err(web_errors::ErrorForbidden(ERR_ACCESS_DENIED));
     }
+    validate_service(&data).map_err(web_errors::ErrorBadRequest)?;
+    if conflicts_with_default(&profile.services, &data, None) {
+        return Err(web_errors::ErrorBadRequest(ERR_DEFAULT_SENDER_EXISTED));
+    }
 
     let record = model
         .add_service(&uid, data)
@@ -108,16 +160,29 @@ async fn update_service(
     if !data.validate_properties(&service) {
         return Err(web_errors::ErrorForbidden(ERR_ACCESS_DENIED));
     }
+    validate_service(&data).map_err(web_errors::ErrorBadRequest)?;
 
     let service_id = ObjectId::with_string(&service_id)
         .map_err(|_| web_errors::ErrorBadRequest("Invalid service_id"))?;
 
     let mut service_profile = profile
         .services
-        .into_iter()
+        .iter()
         .find(|s| s._id == service_id)
+        .cloned()
         .ok_or(web_errors::ErrorNotFound("Service not found"))?;
 
+    if profile
+        .services
+        .iter()
+        .any(|s| s._id != service_id && s.service.conflicts_with(&data))
+    {
+        return Err(web_errors::ErrorConflict("Service already existed"));
+    }
+    if conflicts_with_default(&profile.services, &data, Some(&service_id)) {
+        return Err(web_errors::ErrorBadRequest(ERR_DEFAULT_SENDER_EXISTED));
+    }
+
     if variant_eq(&service_profile.service, &data) {
         service_profile.service = data;
 
@@ -162,9 +227,92 @@ async fn remove_service(
     }
 }
 
+#[post("/profile/{uid}/{service_id}/test")]
+async fn test_service(
+    Path((uid, service_id)): Path<(String, String)>,
+    auth: Auth,
+    model: Model,
+    service: ServiceProfile,
+    push_service: web::Data<EmailNotifyService>,
+    request: Option<Json<ConnectionTestRequest>>,
+) -> Result<Json<ConnectionReport>> {
+    let profile: UserProfile = model
+        .allow_self_or_admin_access(&auth, service.access, &uid)
+        .await?;
+
+    let service_id = ObjectId::with_string(&service_id)
+        .map_err(|_| web_errors::ErrorBadRequest("Invalid service_id"))?;
+
+    let record = profile
+        .services
+        .into_iter()
+        .find(|s| s._id == service_id)
+        .ok_or(web_errors::ErrorNotFound("Service not found"))?;
+    let notify_profile = match record.service {
+        Service::EmailNotify(notify_profile) => notify_profile,
+        _ => return Err(web_errors::ErrorBadRequest("Only email notify service can be tested")),
+    };
+
+    let recipient = request.and_then(|Json(request)| request.to);
+    if recipient.as_ref().map_or(false, |addr| !is_valid_address(addr)) {
+        return Err(web_errors::ErrorBadRequest("Invalid receiver address"));
+    }
+
+    // Connecting to SMTP server is blocking.
+    let push_service = push_service.get_ref().clone();
+    let report = web::block(move || Ok::<_, ()>(push_service.test_connection(&notify_profile, recipient.as_deref())))
+        .await
+        .map_err(web_errors::ErrorInternalServerError)?;
+
+    Ok(Json(report))
+}
+
+#[post("/profile/{uid}/purge")]
+async fn purge_notifications(
+    Path(uid): Path<String>,
+    model: Model,
+    service: ServiceProfile,
+    Json(request): Json<PurgeRequest>,
+) -> Result<Json<PurgeResult>> {
+    let profile: UserProfile = model.allow_admin_access(service.access, &uid).await?;
+
+    if request.older_than_days < 0 {
+        return Err(web_errors::ErrorBadRequest("Invalid age"));
+    }
+    let service_ids: Vec<ObjectId> = profile
+        .services
+        .into_iter()
+        .filter(|s| match s.service {
+            Service::EmailNotify(_) => true,
+            _ => false,
+        })
+        .map(|s| s._id)
+        .collect();
+    if service_ids.is_empty() {
+        return Err(web_errors::ErrorNotFound("Service not found"));
+    }
+
+    let before = days_ago(request.older_than_days);
+    let mut result = PurgeResult { stripped: 0, deleted: 0 };
+    if request.body_only {
+        result.stripped = model.purge_notification_bodies(&service_ids, before)
+            .await
+            .map_err(handle_model_err)?;
+    } else {
+        result.deleted = model.delete_notifications_before(&service_ids, before)
+            .await
+            .map_err(handle_model_err)?;
+    }
+    log::info!("Purged notifications of {} older than {} days", &uid, request.older_than_days);
+
+    Ok(Json(result))
+}
+
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(get_profile)
         .service(add_service)
         .service(update_service)
-        .service(remove_service);
+        .service(remove_service)
+        .service(test_service)
+        .service(purge_notifications);
 }
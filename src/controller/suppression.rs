@@ -0,0 +1,99 @@
+use actix_web::{HttpResponse, Result, delete, error as web_errors, get, post, web::Data, web::Json, web::Path, web::{Query, ServiceConfig}};
+use serde::{Deserialize, Serialize};
+
+use crate::model::{self, Suppression, SuppressionReason, UserProfile};
+
+use super::extractor::ExtensionMove;
+use super::notify::{is_valid_address, notify_service_id};
+
+#[derive(Deserialize)]
+struct SuppressionQuery {
+    /// The suppression list belongs to the sender profile, use the default one if not specified.
+    sender: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SuppressionRequest {
+    address: String,
+    note: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PubSuppression {
+    address: String,
+    reason: SuppressionReason,
+    detail: Option<String>,
+    created_at: String,
+}
+
+impl From<Suppression> for PubSuppression {
+    fn from(suppression: Suppression) -> Self {
+        Self {
+            address: suppression.address,
+            reason: suppression.reason,
+            detail: suppression.detail,
+            created_at: suppression.created_at.to_rfc3339(),
+        }
+    }
+}
+
+fn handel_model_error(err: model::Error) -> actix_web::Error {
+    match err {
+        model::Error::NoRecord => web_errors::ErrorNotFound("Suppression not found"),
+        err => web_errors::ErrorInternalServerError(err)
+    }
+}
+
+type Auth = ExtensionMove<UserProfile>;
+type Model = Data<model::Model>;
+
+#[get("")]
+async fn list_suppressions(auth: Auth, model: Model, Query(params): Query<SuppressionQuery>) -> Result<Json<Vec<PubSuppression>>> {
+    let service_id = notify_service_id(&auth, params.sender.as_deref())?;
+    let suppressions = model.get_suppressions(&service_id)
+        .await
+        .map_err(handel_model_error)?;
+
+    Ok(Json(suppressions.into_iter().map(PubSuppression::from).collect()))
+}
+
+#[post("")]
+async fn add_suppression(
+    auth: Auth,
+    model: Model,
+    Query(params): Query<SuppressionQuery>,
+    Json(request): Json<SuppressionRequest>,
+) -> Result<Json<PubSuppression>> {
+    let service_id = notify_service_id(&auth, params.sender.as_deref())?;
+    if !is_valid_address(&request.address) {
+        return Err(web_errors::ErrorBadRequest("Invalid address"));
+    }
+
+    let suppression = model
+        .add_suppression(&service_id, &request.address, SuppressionReason::Manual, request.note)
+        .await
+        .map_err(handel_model_error)?;
+
+    Ok(Json(PubSuppression::from(suppression)))
+}
+
+#[delete("/{address}")]
+async fn remove_suppression(
+    Path(address): Path<String>,
+    auth: Auth,
+    model: Model,
+    Query(params): Query<SuppressionQuery>,
+) -> Result<HttpResponse> {
+    let service_id = notify_service_id(&auth, params.sender.as_deref())?;
+    match model.remove_suppression(&service_id, &address).await {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(model::Error::NoRecord) => Ok(HttpResponse::NoContent().finish()),
+        Err(err) => Err(handel_model_error(err)),
+    }
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.service(list_suppressions)
+        .service(add_suppression)
+        .service(remove_suppression);
+}
@@ -0,0 +1,168 @@
+use actix_web::{HttpResponse, Result, delete, error as web_errors, get, put, web::Data, web::Json, web::Path, web::{JsonConfig, Query, ServiceConfig}};
+use serde::{Deserialize, Serialize};
+
+use crate::model::{self, NotifyTemplate, UserProfile};
+use crate::service::{Locale, Template};
+
+use super::extractor::ExtensionMove;
+use super::notify::notify_service_id;
+
+#[derive(Deserialize)]
+struct TemplateContent {
+    subject: String,
+    html: Option<String>,
+    text: Option<String>,
+}
+
+impl TemplateContent {
+    fn validate(&self) -> std::result::Result<(), String> {
+        if self.html.is_none() && self.text.is_none() {
+            return Err("Missing template content".to_string());
+        }
+        if self.subject.contains(|c| c == '\r' || c == '\n') {
+            return Err("Invalid subject".to_string());
+        }
+        let sources = Some(&self.subject).into_iter()
+            .chain(self.html.as_ref())
+            .chain(self.text.as_ref());
+        for source in sources {
+            Template::parse(source).map_err(|err| format!("Template error: {}", err))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct TemplateQuery {
+    locale: Option<String>,
+    version: Option<i32>,
+    /// Templates belong to the sender profile, use the default one if not specified.
+    sender: Option<String>,
+}
+
+impl TemplateQuery {
+    /// The normalized language tag of the variant.
+    fn locale(&self) -> Result<Option<String>> {
+        match &self.locale {
+            Some(tag) => Locale::parse(tag)
+                .map(|locale| Some(locale.tag().to_string()))
+                .ok_or(web_errors::ErrorBadRequest("Invalid locale")),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PubTemplate {
+    name: String,
+    locale: Option<String>,
+    version: i32,
+    subject: String,
+    html: Option<String>,
+    text: Option<String>,
+}
+
+impl From<NotifyTemplate> for PubTemplate {
+    fn from(template: NotifyTemplate) -> Self {
+        Self {
+            name: template.name,
+            locale: template.locale,
+            version: template.version,
+            subject: template.subject,
+            html: template.html,
+            text: template.text,
+        }
+    }
+}
+
+fn handel_model_error(err: model::Error) -> actix_web::Error {
+    match err {
+        model::Error::NoRecord => web_errors::ErrorNotFound("Template not found"),
+        err => web_errors::ErrorInternalServerError(err)
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= TEMPLATE_NAME_MAX_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+type Auth = ExtensionMove<UserProfile>;
+type Model = Data<model::Model>;
+
+const TEMPLATE_NAME_MAX_LEN: usize = 64;
+const JSON_PAYLOAD_LIMIT: usize = 1024 * 1024;
+
+#[get("")]
+async fn list_templates(auth: Auth, model: Model, Query(params): Query<TemplateQuery>) -> Result<Json<Vec<PubTemplate>>> {
+    let service_id = notify_service_id(&auth, params.sender.as_deref())?;
+    let templates = model.get_templates(&service_id)
+        .await
+        .map_err(handel_model_error)?;
+
+    Ok(Json(templates.into_iter().map(PubTemplate::from).collect()))
+}
+
+#[get("/{name}")]
+async fn get_template(
+    Path(name): Path<String>,
+    auth: Auth,
+    model: Model,
+    Query(params): Query<TemplateQuery>,
+) -> Result<Json<PubTemplate>> {
+    let service_id = notify_service_id(&auth, params.sender.as_deref())?;
+    let locale = params.locale()?;
+    let template = model.get_template(&service_id, &name, locale.as_deref(), params.version)
+        .await
+        .map_err(handel_model_error)?;
+
+    Ok(Json(PubTemplate::from(template)))
+}
+
+#[put("/{name}")]
+async fn set_template(
+    Path(name): Path<String>,
+    auth: Auth,
+    model: Model,
+    Query(params): Query<TemplateQuery>,
+    Json(content): Json<TemplateContent>,
+) -> Result<Json<PubTemplate>> {
+    let service_id = notify_service_id(&auth, params.sender.as_deref())?;
+    if !is_valid_name(&name) {
+        return Err(web_errors::ErrorBadRequest("Invalid template name"));
+    }
+    let locale = params.locale()?;
+    content.validate().map_err(web_errors::ErrorBadRequest)?;
+
+    let template = model
+        .add_template_version(&service_id, &name, locale.as_deref(), content.subject, content.html, content.text)
+        .await
+        .map_err(handel_model_error)?;
+
+    Ok(Json(PubTemplate::from(template)))
+}
+
+#[delete("/{name}")]
+async fn delete_template(
+    Path(name): Path<String>,
+    auth: Auth,
+    model: Model,
+    Query(params): Query<TemplateQuery>,
+) -> Result<HttpResponse> {
+    let service_id = notify_service_id(&auth, params.sender.as_deref())?;
+    let locale = params.locale()?;
+    match model.remove_template(&service_id, &name, locale.as_deref()).await {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(model::Error::NoRecord) => Ok(HttpResponse::NoContent().finish()),
+        Err(err) => Err(handel_model_error(err)),
+    }
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.app_data(JsonConfig::default().limit(JSON_PAYLOAD_LIMIT))
+        .service(list_templates)
+        .service(get_template)
+        .service(set_template)
+        .service(delete_template);
+}
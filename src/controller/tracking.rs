@@ -0,0 +1,58 @@
+use actix_web::{HttpResponse, Result, error as web_errors, get, http::header, web::Data, web::{Query, ServiceConfig}};
+use serde::Deserialize;
+
+use crate::model;
+
+#[derive(Deserialize)]
+struct TrackingQuery {
+    token: String,
+}
+
+type Model = Data<model::Model>;
+type EmailNotifyService = Data<crate::service::EmailNotifyService>;
+
+const ERR_INVALID_LINK: &str = "Invalid tracking link";
+
+/// A transparent 1x1 GIF.
+const PIXEL_GIF: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff!\xf9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";
+
+/// Failing to record is not the business of the recipient, only logged.
+fn log_record_error(result: Result<(), model::Error>) {
+    match result {
+        Ok(()) | Err(model::Error::NoRecord) => (),
+        Err(err) => log::warn!("Failed to record the tracking event {:?}", err),
+    }
+}
+
+/// Loaded as the tracking pixel of the notification.
+#[get("/open")]
+async fn open(push_service: EmailNotifyService, model: Model, Query(params): Query<TrackingQuery>) -> Result<HttpResponse> {
+    let message_id = push_service.verify_open(&params.token)
+        .ok_or(web_errors::ErrorBadRequest(ERR_INVALID_LINK))?;
+
+    log_record_error(model.record_notification_open(&message_id).await);
+
+    Ok(HttpResponse::Ok()
+        .content_type("image/gif")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(PIXEL_GIF))
+}
+
+/// Redirects to the original link after the click is recorded.
+#[get("/click")]
+async fn click(push_service: EmailNotifyService, model: Model, Query(params): Query<TrackingQuery>) -> Result<HttpResponse> {
+    let (message_id, url) = push_service.verify_click(&params.token)
+        .ok_or(web_errors::ErrorBadRequest(ERR_INVALID_LINK))?;
+
+    log_record_error(model.record_notification_click(&message_id, &url).await);
+
+    Ok(HttpResponse::Found()
+        .header(header::LOCATION, url)
+        .header(header::CACHE_CONTROL, "no-store")
+        .finish())
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.service(open)
+        .service(click);
+}
@@ -0,0 +1,55 @@
+use actix_web::{HttpResponse, Result, error as web_errors, get, post, web::Data, web::{Query, ServiceConfig}};
+use serde::Deserialize;
+
+use crate::model::{self, SuppressionReason};
+
+#[derive(Deserialize)]
+struct UnsubscribeQuery {
+    token: String,
+}
+
+type Model = Data<model::Model>;
+type EmailNotifyService = Data<crate::service::EmailNotifyService>;
+
+const ERR_INVALID_LINK: &str = "Invalid unsubscribe link";
+
+fn html_page(body: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!("<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Unsubscribe</title></head><body>{}</body></html>", body))
+}
+
+/// Opened from the link in a browser, ask for confirmation rather than unsubscribing,
+/// since the link may be visited by the scanners of the mail server.
+#[get("")]
+async fn confirm_page(push_service: EmailNotifyService, Query(params): Query<UnsubscribeQuery>) -> Result<HttpResponse> {
+    push_service.verify_unsubscribe(&params.token)
+        .ok_or(web_errors::ErrorBadRequest(ERR_INVALID_LINK))?;
+
+    Ok(html_page(
+        "<form method=\"post\"><p>Stop receiving these emails?</p><button type=\"submit\">Unsubscribe</button></form>"
+    ))
+}
+
+/// The one-click unsubscribe of RFC 8058, posted by the mail client or the confirmation page.
+#[post("")]
+async fn unsubscribe(
+    push_service: EmailNotifyService,
+    model: Model,
+    Query(params): Query<UnsubscribeQuery>,
+) -> Result<HttpResponse> {
+    let (sender_profile, address) = push_service.verify_unsubscribe(&params.token)
+        .ok_or(web_errors::ErrorBadRequest(ERR_INVALID_LINK))?;
+
+    model.add_suppression(&sender_profile, &address, SuppressionReason::Unsubscribed, None)
+        .await
+        .map_err(web_errors::ErrorInternalServerError)?;
+    log::info!("{} unsubscribed from the notifications of {}", &address, &sender_profile);
+
+    Ok(html_page("<p>You are unsubscribed.</p>"))
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.service(confirm_page)
+        .service(unsubscribe);
+}
@@ -25,13 +25,33 @@ use std::time::Duration;
 
 use actix_web::{App, HttpServer, dev::Server, middleware::Logger};
 use env_logger::Env;
-use model::Model;
-use service::EmailNotifyService;
+use model::{Model, Retention};
+use service::{BounceReceiver, EmailNotifyService, TrackingLinks, UnsubscribeLinks, VerpAddresses};
 
-async fn start_server(db_addr: &str, db_name: &str, listen_addr: &str) -> std::io::Result<Server> {
+/// Settings of the server, built from the command line.
+#[derive(Default)]
+struct ServerContext<'a> {
+    db_addr: &'a str,
+    db_name: &'a str,
+    listen_addr: &'a str,
+    retention: Retention,
+    unsubscribe: Option<UnsubscribeLinks>,
+    tracking: Option<TrackingLinks>,
+    verp: Option<VerpAddresses>,
+    /// The address to receive the bounces on and the domain of the bounce addresses.
+    bounce: Option<(&'a str, &'a str)>,
+}
+
+async fn start_server(context: ServerContext<'_>) -> std::io::Result<Server> {
 
-    let model = Model::new(db_addr, db_name).await.unwrap();
-    let notify_service = EmailNotifyService::new(model.clone(), Duration::from_secs(5));
+    let model = Model::new(context.db_addr, context.db_name).await.unwrap();
+    let notify_service = EmailNotifyService::new(model.clone(), Duration::from_secs(5), context.unsubscribe, context.tracking, context.verp);
+    service::start_purge_task(model.clone(), context.retention);
+    if let Some((bounce_addr, bounce_domain)) = context.bounce {
+        BounceReceiver::new(model.clone(), &notify_service, bounce_domain)
+            .listen(bounce_addr)
+            .await?;
+    }
 
     let server = HttpServer::new(move || {
         App::new()
@@ -42,7 +62,7 @@ async fn start_server(db_addr: &str, db_name: &str, listen_addr: &str) -> std::i
             .wrap(Logger::default())
             .configure(controller::config)
     })
-    .bind(listen_addr)?
+    .bind(context.listen_addr)?
     .run();
     Ok(server)
 }
@@ -59,11 +79,37 @@ async fn main() -> std::io::Result<()> {
         .arg("-l, --listen=[LOCAL_ADDR] 'Specific the local address [<host>:<port>] on which HTTP server will listen'")
         .arg("--db-addr=[DB_ADDR] 'Specific address of the mongodb service'")
         .arg("--db-name=[DB_NAME] 'Specific the mongodb db name to use for this service'")
+        .arg("--retain-body-days=[DAYS] 'Strip the body of notifications after days, unless overridden by the sender profile'")
+        .arg("--retain-days=[DAYS] 'Delete notifications after days, unless overridden by the sender profile'")
+        .arg("--public-url=[URL] 'Specific the public URL of this service, used by the unsubscribe and tracking links'")
+        .arg("--unsubscribe-secret=[SECRET] 'Specific the key to sign the unsubscribe links, no link is added without it'")
+        .arg("--tracking-secret=[SECRET] 'Specific the key to sign the tracking links, no open or click is tracked without it'")
+        .arg("--bounce-listen=[ADDR] 'Specific the address to receive the bounces by SMTP or LMTP, no bounce is received without it'")
+        .arg("--bounce-domain=[DOMAIN] 'Specific the domain of the bounce addresses, only the mails to it are accepted'")
+        .arg("--bounce-secret=[SECRET] 'Specific the key to sign the VERP addresses, no VERP address is used without it'")
         .get_matches();
 
     let local_addr = matches.value_of("listen").unwrap_or("localhost:5000");
     let db_name = matches.value_of("db-name").unwrap_or("sar-notify");
     let db_addr = matches.value_of("db-addr").unwrap_or("mongodb://mongo");
+    let retention = Retention {
+        body_days: matches.value_of("retain-body-days").map(|days| days.parse().expect("Invalid 'retain-body-days'")),
+        metadata_days: matches.value_of("retain-days").map(|days| days.parse().expect("Invalid 'retain-days'")),
+    };
+    assert!(retention.is_valid(), "'retain-body-days' and 'retain-days' must be at least 1");
+    let unsubscribe = matches.value_of("unsubscribe-secret").map(|secret| {
+        let public_url = matches.value_of("public-url").expect("Missing 'public-url' for the unsubscribe links");
+        UnsubscribeLinks::new(public_url, secret)
+    });
+    let tracking = matches.value_of("tracking-secret").map(|secret| {
+        let public_url = matches.value_of("public-url").expect("Missing 'public-url' for the tracking links");
+        TrackingLinks::new(public_url, secret)
+    });
+    let verp = matches.value_of("bounce-secret").map(VerpAddresses::new);
+    let bounce = matches.value_of("bounce-listen").map(|addr| {
+        let domain = matches.value_of("bounce-domain").expect("Missing 'bounce-domain' for the bounce receiver");
+        (addr, domain)
+    });
         
     if matches.is_present("init") {
         let model = Model::new(db_addr, db_name).await.unwrap();
@@ -77,5 +123,15 @@ async fn main() -> std::io::Result<()> {
     log::info!("Use db '{}'", db_name);
 
  
-    start_server(db_addr, db_name, local_addr).await?.await
+    let context = ServerContext {
+        db_addr,
+        db_name,
+        listen_addr: local_addr,
+        retention,
+        unsubscribe,
+        tracking,
+        verp,
+        bounce,
+    };
+    start_server(context).await?.await
 }
@@ -63,12 +63,19 @@ async fn get_profile(request: &ServiceRequest) -> Result<model::UserProfile, act
     }
 }
 
+/// Paths accessible without authentication, authorized by their own means such as a signed token.
+const PUBLIC_PATHS: &[&str] = &["/unsubscribe", "/track/open", "/track/click"];
+
 pub async fn access_chk<S, B>(request: ServiceRequest, mut service: Rc<RefCell<S>>) -> Result<ServiceResponse<B>, actix_web::Error>
 where
     S: ServiceT<B> + 'static,
     S::Future: 'static,
     B: MessageBody
 {
+    if PUBLIC_PATHS.contains(&request.path()) {
+        return service.call(request).await;
+    }
+
     let profile = get_profile(&request).await;
     match profile {
         Ok(profile) => {
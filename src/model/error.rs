@@ -1,6 +1,10 @@
 use std::fmt::{Display};
 
 use mongodb::bson;
+use mongodb::error::{ErrorKind, WriteFailure};
+
+/// The server error code of a write rejected by a unique index.
+const DUPLICATE_KEY: i32 = 11000;
 
 #[derive(Debug)]
 pub enum Error {
@@ -38,6 +42,24 @@ impl From<mongodb::error::Error> for Error {
 pub fn mongo_error(err: mongodb::error::Error) -> Error {
     Error::MongoError(err)
 }
+/// The write is rejected by a unique index.
+pub fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    match err.kind.as_ref() {
+        ErrorKind::CommandError(err) => err.code == DUPLICATE_KEY,
+        ErrorKind::WriteError(WriteFailure::WriteError(err)) => err.code == DUPLICATE_KEY,
+        _ => false,
+    }
+}
+/// Index of each write in the bulk that failed, `None` if it's not known which writes failed.
+pub fn failed_write_indices(err: &mongodb::error::Error) -> Option<Vec<usize>> {
+    match err.kind.as_ref() {
+        ErrorKind::BulkWriteError(failure) if failure.write_concern_error.is_none() => {
+            let errors = failure.write_errors.as_ref()?;
+            Some(errors.iter().map(|err| err.index).collect())
+        }
+        _ => None,
+    }
+}
 pub fn bson_de_error(err: bson::de::Error) -> Error {
     Error::BsonDeserializeError(err)
 }
@@ -31,6 +31,12 @@ impl Model {
             .await
             .map_err(mongo_error)?;
 
+        info!("Create notify indexes...");
+        self.create_notify_indexes().await?;
+
+        info!("Create suppression indexes...");
+        self.create_suppression_indexes().await?;
+
         info!("Init root user...");
 
         self.remove_user("root").await?;
@@ -7,6 +7,8 @@ mod notify;
 mod init;
 mod service;
 mod profile;
+mod template;
+mod suppression;
 
 use std::time::Duration;
 
@@ -37,5 +39,7 @@ impl Model {
 pub use profile::{ UserProfile, Access, Service, ServiceRecord, ExtractProfile, ValidateProfile };
 pub use access::{ AccessManagerProfile };
 pub use error::{ Error };
-pub use notify::{NotifyProfile, EmailNotify, MailData, NotifyState};
-pub use service::{ ServiceManagerProfile };
\ No newline at end of file
+pub use notify::{NotifyProfile, NotifyPriority, RateLimit, Relay, Retention, days_ago, EmailNotify, MailData, NotifyState, NotifyStatusKind, NotifyQuery, NotifyStatistics, RecipientState, Attachment, AttachmentDisposition};
+pub use service::{ ServiceManagerProfile };
+pub use template::{NotifyTemplate, TemplateRef};
+pub use suppression::{Suppression, SuppressionReason};
\ No newline at end of file
@@ -1,11 +1,64 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use bson::doc;
+use chrono::Utc;
 use mongodb::{ bson::oid::ObjectId};
 use mongodb::bson;
+use mongodb::options::{AggregateOptions, FindOptions, InsertManyOptions, UpdateOptions};
 use serde::{Serialize, Deserialize};
-use tokio::stream::StreamExt;
+use tokio::stream::{Stream, StreamExt};
 use uuid::Uuid;
 
-use super::{Error, ExtractProfile, Model, Service, ValidateProfile, error::mongo_error};
+use super::{Error, ExtractProfile, Model, Service, TemplateRef, ValidateProfile, error::{doc_error, failed_write_indices, is_duplicate_key, mongo_error}};
+use crate::utils::one_or_many;
+
+/// An SMTP server to send the notifications through.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Relay {
+    pub smtp_address: String,
+    pub tls: bool,
+    pub username: String,
+    pub password: String,
+}
+
+/// At most `max_messages` notifications are sent in any `interval` seconds.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RateLimit {
+    pub max_messages: i32,
+    pub interval: i64,
+}
+
+/// Days to keep the notifications after they are queued, forever if not set.
+/// The pending notifications are always kept.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub struct Retention {
+    /// The body and attachments are stripped after this number of days.
+    #[serde(default)]
+    pub body_days: Option<i32>,
+    /// The whole notification is deleted after this number of days.
+    #[serde(default)]
+    pub metadata_days: Option<i32>,
+}
+
+impl Retention {
+    /// The days not set in this retention are taken from `fallback`.
+    pub fn or(self, fallback: Retention) -> Retention {
+        Retention {
+            body_days: self.body_days.or(fallback.body_days),
+            metadata_days: self.metadata_days.or(fallback.metadata_days),
+        }
+    }
+
+    /// The days set are at least 1, a shorter one would purge all the history right away.
+    pub fn is_valid(&self) -> bool {
+        self.body_days.map_or(true, |days| days >= 1) && self.metadata_days.map_or(true, |days| days >= 1)
+    }
+}
+
+/// The time `days` before now.
+pub fn days_ago(days: i32) -> SystemTime {
+    SystemTime::now() - Duration::from_secs(days.max(0) as u64 * 24 * 60 * 60)
+}
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct NotifyProfile {
@@ -15,6 +68,54 @@ pub struct NotifyProfile {
     pub password: String,
     pub email_address: String,
     pub name: String,
+    /// Limit of the total decoded size in bytes of the attachments in a notification.
+    #[serde(default)]
+    pub max_attachments_size: Option<i64>,
+    /// Name to choose this profile by the `sender` of a notify request,
+    /// unique among the `EmailNotify` services of a user.
+    #[serde(default)]
+    pub sender: Option<String>,
+    /// Used for the requests without `sender`.
+    #[serde(default)]
+    pub default: bool,
+    /// Tried in order if the primary relay is unreachable or defers the notification.
+    #[serde(default)]
+    pub fallback_relays: Vec<Relay>,
+    /// Notifications over the limit are held until they can be sent.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    /// Status change events of the notifications are posted to this URL,
+    /// unless another URL is specified by the notify request.
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    /// Key to sign the webhook events with HMAC-SHA256.
+    #[serde(default)]
+    pub webhook_secret: Option<String>,
+    /// Overrides the global retention of the notification history.
+    #[serde(default)]
+    pub retention: Option<Retention>,
+    /// Send with the envelope sender `bounce+{message_id}@{verp_domain}` instead of `email_address`,
+    /// the bounces to it are matched to the notification by the address.
+    #[serde(default)]
+    pub verp_domain: Option<String>,
+    /// Rewrite the links of the HTML bodies and add a tracking pixel to record the opens and clicks.
+    #[serde(default)]
+    pub tracking: bool,
+}
+
+impl NotifyProfile {
+    /// The primary relay followed by the fallback relays.
+    pub fn relays(&self) -> Vec<Relay> {
+        let primary = Relay {
+            smtp_address: self.smtp_address.clone(),
+            tls: self.tls,
+            username: self.username.clone(),
+            password: self.password.clone(),
+        };
+        Some(primary).into_iter()
+            .chain(self.fallback_relays.iter().cloned())
+            .collect()
+    }
 }
 
 impl ExtractProfile<NotifyProfile> for NotifyProfile {
@@ -26,12 +127,44 @@ impl ExtractProfile<NotifyProfile> for NotifyProfile {
     }
 }
 
+/// Pending notifications of higher priority are sent first.
+#[derive(Serialize, Deserialize, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyPriority {
+    High,
+    Normal,
+    Bulk,
+}
+
+impl Default for NotifyPriority {
+    fn default() -> Self {
+        NotifyPriority::Normal
+    }
+}
+
+impl NotifyPriority {
+    /// Higher is sent first.
+    fn rank(self) -> i32 {
+        match self {
+            NotifyPriority::High => 2,
+            NotifyPriority::Normal => 1,
+            NotifyPriority::Bulk => 0,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq)]
 pub enum NotifyState {
     Pending,
     Sent,
     /// (pub_error, inner_error)
     Error(String, String),
+    /// Not sent since the recipient is on the suppression list of the sender profile,
+    /// a notification is suppressed if all of its recipients are.
+    Suppressed,
+    /// Accepted by the relay but returned by a delivery status notification,
+    /// with the status and diagnostic of the report. A notification is bounced if none of its recipients is sent.
+    Bounced(String),
 }
 
 impl NotifyState {
@@ -53,14 +186,81 @@ impl NotifyState {
             _ => false,
         }
     }
+    pub fn is_bounced(&self) -> bool {
+        match self {
+            NotifyState::Bounced(_) => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct MailData {
-    pub to: String,
+    #[serde(deserialize_with = "one_or_many")]
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub cc: Vec<String>,
+    #[serde(default)]
+    pub bcc: Vec<String>,
     pub subject: String,
+    /// Content type of `body`, only used without `html` and `text`.
+    #[serde(default)]
     pub content_type: String,
+    #[serde(default)]
     pub body: String,
+    /// HTML and plain text alternatives of the content.
+    #[serde(default)]
+    pub html: Option<String>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// Language tag of the body, sent as `Content-Language` header.
+    #[serde(default)]
+    pub content_language: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum AttachmentDisposition {
+    Attachment,
+    Inline,
+}
+
+impl Default for AttachmentDisposition {
+    fn default() -> Self {
+        AttachmentDisposition::Attachment
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    #[serde(default)]
+    pub disposition: AttachmentDisposition,
+    /// Required by inline attachments to be referred in the mail body.
+    pub content_id: Option<String>,
+    /// Base64 encoded content.
+    pub content: String,
+}
+
+impl MailData {
+    /// All the envelope recipients without duplication.
+    pub fn recipients(&self) -> Vec<&str> {
+        let mut recipients: Vec<&str> = Vec::new();
+        for addr in self.to.iter().chain(self.cc.iter()).chain(self.bcc.iter()) {
+            if !recipients.contains(&addr.as_str()) {
+                recipients.push(addr);
+            }
+        }
+        recipients
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecipientState {
+    pub address: String,
+    pub status: NotifyState,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -70,11 +270,207 @@ pub struct EmailNotify {
     pub status: NotifyState,
     pub sender_profile: ObjectId,
     pub mail: MailData,
+    pub idempotency_key: Option<String>,
+    /// The template rendered into this notification.
+    #[serde(default)]
+    pub template: Option<TemplateRef>,
+    /// Delivery status of each envelope recipient, empty until the notification is sent.
+    #[serde(default)]
+    pub recipients: Vec<RecipientState>,
+    /// Address of the relay which accepted the notification.
+    #[serde(default)]
+    pub relay: Option<String>,
+    /// A pending notification held by the rate limit is not sent before this time.
+    #[serde(default)]
+    pub next_attempt_at: Option<bson::DateTime>,
+    #[serde(default)]
+    pub priority: NotifyPriority,
+    /// `priority` as a number to sort the pending notifications by in the storage query.
+    #[serde(default)]
+    priority_rank: i32,
+    /// Overrides the `webhook_url` of the sender profile.
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    /// Number of attempts to send the notification.
+    #[serde(default)]
+    pub attempts: i32,
+    /// Not recorded for the notifications queued before, use the time of `_id` instead.
+    #[serde(default)]
+    pub created_at: Option<bson::DateTime>,
+    #[serde(default)]
+    pub first_attempt_at: Option<bson::DateTime>,
+    #[serde(default)]
+    pub sent_at: Option<bson::DateTime>,
+    /// The final SMTP reply of the last attempt.
+    #[serde(default)]
+    pub reply_code: Option<i32>,
+    #[serde(default)]
+    pub reply_text: Option<String>,
+    /// The body and attachments are stripped by the retention.
+    #[serde(default)]
+    pub body_purged: bool,
+    /// The opens and clicks are tracked, see `NotifyProfile::tracking`.
+    #[serde(default)]
+    pub tracked: bool,
+    /// Number of times the tracking pixel is loaded.
+    #[serde(default)]
+    pub opens: i32,
+    #[serde(default)]
+    pub clicks: i32,
+    #[serde(default)]
+    pub first_opened_at: Option<bson::DateTime>,
+    #[serde(default)]
+    pub first_clicked_at: Option<bson::DateTime>,
+    /// The distinct links clicked.
+    #[serde(default)]
+    pub clicked_links: Vec<String>,
+}
+
+impl EmailNotify {
+    pub fn set_priority(&mut self, priority: NotifyPriority) {
+        self.priority = priority;
+        self.priority_rank = priority.rank();
+    }
+
+    /// Give up the notification without sending, all of its recipients are suppressed.
+    pub fn set_suppressed(&mut self) {
+        self.status = NotifyState::Suppressed;
+        self.recipients = self.mail.recipients()
+            .into_iter()
+            .map(|address| RecipientState {
+                address: address.to_string(),
+                status: NotifyState::Suppressed,
+            })
+            .collect();
+    }
 }
 impl ValidateProfile for NotifyProfile {
 }
 
+/// Status of the notifications to find, regardless of the error.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NotifyStatusKind {
+    Pending,
+    Sent,
+    Error,
+    Suppressed,
+    Bounced,
+}
+
+/// A page of the notification history, all the conditions are applied in the storage query.
+pub struct NotifyQuery {
+    pub status: Option<NotifyStatusKind>,
+    /// Only the notifications after this one in the sort order.
+    pub after: Option<ObjectId>,
+    /// Queued at or after this time.
+    pub since: Option<SystemTime>,
+    /// Queued before this time.
+    pub until: Option<SystemTime>,
+    /// Case-insensitive substring of any receiver address.
+    pub recipient: Option<String>,
+    /// Case-insensitive substring of the subject.
+    pub subject: Option<String>,
+    /// Newest first if `true`.
+    pub descending: bool,
+    /// No limit if `0`.
+    pub limit: i64,
+}
+
+impl NotifyQuery {
+    fn filter(&self, service_ids: &[ObjectId]) -> bson::Document {
+        let mut query = doc! {
+            "sender_profile": {
+                "$in": service_ids,
+            },
+        };
+        match self.status {
+            Some(NotifyStatusKind::Pending) => query.insert("status", bson::to_bson(&NotifyState::Pending).unwrap()),
+            Some(NotifyStatusKind::Sent) => query.insert("status", bson::to_bson(&NotifyState::Sent).unwrap()),
+            Some(NotifyStatusKind::Error) => query.insert("status.Error", doc! { "$exists": true }),
+            Some(NotifyStatusKind::Suppressed) => query.insert("status", bson::to_bson(&NotifyState::Suppressed).unwrap()),
+            Some(NotifyStatusKind::Bounced) => query.insert("status.Bounced", doc! { "$exists": true }),
+            None => None,
+        };
+
+        let mut id_range = bson::Document::new();
+        if let Some(since) = self.since {
+            id_range.insert("$gte", object_id_since(since));
+        }
+        if let Some(until) = self.until {
+            id_range.insert("$lt", object_id_since(until));
+        }
+        if let Some(after) = &self.after {
+            id_range.insert(if self.descending { "$lt" } else { "$gt" }, after.clone());
+        }
+        if !id_range.is_empty() {
+            query.insert("_id", id_range);
+        }
+
+        if let Some(recipient) = &self.recipient {
+            let pattern = contains_pattern(recipient);
+            query.insert("$or", vec![
+                doc! { "mail.to": pattern.clone() },
+                doc! { "mail.cc": pattern.clone() },
+                doc! { "mail.bcc": pattern },
+            ]);
+        }
+        if let Some(subject) = &self.subject {
+            query.insert("mail.subject", contains_pattern(subject));
+        }
+        query
+    }
+}
+
+/// Case-insensitive regex matches the string containing `text`.
+fn contains_pattern(text: &str) -> bson::Regex {
+    let mut pattern = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    bson::Regex {
+        pattern,
+        options: "i".to_string(),
+    }
+}
+
+/// Delivery statistics of the notifications queued in a time range.
+#[derive(Default, Debug)]
+pub struct NotifyStatistics {
+    pub pending: i64,
+    pub sent: i64,
+    pub error: i64,
+    pub suppressed: i64,
+    pub bounced: i64,
+    /// Number of the notifications with the opens and clicks tracked.
+    pub tracked: i64,
+    /// Number of the tracked notifications opened, including the ones clicked without loading the pixel.
+    pub opened: i64,
+    pub clicked: i64,
+    /// Mean milliseconds from queued to sent, `None` if nothing is sent.
+    pub mean_time_to_send: Option<f64>,
+    /// 95th percentile milliseconds from queued to sent, `None` if nothing is sent.
+    pub p95_time_to_send: Option<i64>,
+    /// The most frequent public errors with their count, most frequent first.
+    pub top_errors: Vec<(String, i64)>,
+    /// The most frequent recipient domains with their count, most frequent first.
+    pub top_domains: Vec<(String, i64)>,
+}
+
 const COLLECTION_NOTIFY: &str = "notify";
+/// The idempotency keys claimed by the sender profiles, expired by a TTL index after `IDEMPOTENCY_WINDOW`.
+const COLLECTION_IDEMPOTENCY_KEY: &str = "idempotency_key";
+
+/// A repeated idempotency key is only recognized within this duration.
+const IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+/// Attempts and interval of waiting for the notification of a claimed idempotency key to be inserted.
+const CLAIM_WAIT_ATTEMPTS: usize = 10;
+const CLAIM_WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Number of entries in the top lists of the statistics.
+const STATS_TOP_ENTRIES: i64 = 10;
 
 impl Model {
     pub fn new_email_notify(&self, sender_profile: ObjectId, mail: MailData, sender_addr: &str) -> EmailNotify {
@@ -85,30 +481,105 @@ impl Model {
             status: NotifyState::Pending,
             sender_profile,
             mail,
+            idempotency_key: None,
+            template: None,
+            recipients: Vec::new(),
+            relay: None,
+            next_attempt_at: None,
+            priority: NotifyPriority::Normal,
+            priority_rank: NotifyPriority::Normal.rank(),
+            webhook_url: None,
+            attempts: 0,
+            created_at: Some(Utc::now().into()),
+            first_attempt_at: None,
+            sent_at: None,
+            reply_code: None,
+            reply_text: None,
+            body_purged: false,
+            tracked: false,
+            opens: 0,
+            clicks: 0,
+            first_opened_at: None,
+            first_clicked_at: None,
+            clicked_links: Vec::new(),
         }
     }
-    pub async fn get_all_notifications_by_service(&self, service_id: &ObjectId) -> Result<Vec<EmailNotify>, Error> {
-        let coll = self.db.collection(COLLECTION_NOTIFY);
-        let query = doc!{
-            "sender_profile": service_id
+    /// Index for the history of the sender profiles, in the order they are queued,
+    /// for the `Message-ID` referred by the bounces, and for the claims of the idempotency keys.
+    pub(super) async fn create_notify_indexes(&self) -> Result<(), Error> {
+        let command = doc! {
+            "createIndexes": COLLECTION_NOTIFY,
+            "indexes": [
+                {
+                    "key": { "sender_profile": 1, "_id": -1 },
+                    "name": "sender_profile_history",
+                },
+                {
+                    "key": { "message_id": 1 },
+                    "name": "message_id",
+                },
+                {
+                    "key": { "status": 1, "priority_rank": -1, "_id": 1 },
+                    "name": "pending_queue",
+                },
+                {
+                    "key": { "sender_profile": 1, "idempotency_key": 1, "_id": -1 },
+                    "name": "idempotency_key",
+                    "partialFilterExpression": { "idempotency_key": { "$type": "string" } },
+                },
+            ],
         };
-        let result = coll.find(query, None)
-            .await
-            .map_err(mongo_error)?;
-        let notifications: Vec<EmailNotify> = result
-            .filter_map(|doc| doc.ok().and_then(|d| bson::from_document(d).ok()))
+        self.db.run_command(command, None).await.map_err(mongo_error)?;
+
+        // The claims are unique by `_id`, the expired ones are removed by the TTL monitor.
+        let command = doc! {
+            "createIndexes": COLLECTION_IDEMPOTENCY_KEY,
+            "indexes": [
+                {
+                    "key": { "claimed_at": 1 },
+                    "name": "claim_expiry",
+                    "expireAfterSeconds": IDEMPOTENCY_WINDOW.as_secs() as i64,
+                },
+            ],
+        };
+        self.db.run_command(command, None).await.map_err(mongo_error)?;
+        Ok(())
+    }
+
+    /// A page of the notifications of the sender profiles, sorted by the time they are queued.
+    pub async fn find_notifications(&self, service_ids: &[ObjectId], query: &NotifyQuery) -> Result<Vec<EmailNotify>, Error> {
+        let notifications: Vec<EmailNotify> = self.find_notifications_stream(service_ids, query)
+            .await?
             .collect()
             .await;
 
         Ok(notifications)
     }
+
+    /// Same as `find_notifications`, but the notifications are fetched from the cursor as the stream is polled.
+    pub async fn find_notifications_stream(&self, service_ids: &[ObjectId], query: &NotifyQuery) -> Result<impl Stream<Item = EmailNotify>, Error> {
+        let coll = self.db.collection(COLLECTION_NOTIFY);
+        let order = if query.descending { -1 } else { 1 };
+        let mut options = FindOptions::default();
+        options.sort = Some(doc! { "_id": order });
+        options.limit = Some(query.limit).filter(|limit| *limit > 0);
+
+        let result = coll.find(query.filter(service_ids), Some(options))
+            .await
+            .map_err(mongo_error)?;
+
+        Ok(result.filter_map(|doc| doc.ok().and_then(|d| bson::from_document(d).ok())))
+    }
     
+    /// Pending notifications in order of priority, then in the order they are queued.
     pub async fn get_pending_notifications(&self)  -> Result<Vec<EmailNotify>, Error> {
         let coll = self.db.collection(COLLECTION_NOTIFY);
         let query = doc! {
             "status": bson::to_bson(&NotifyState::Pending).unwrap(),
         };
-        let result = coll.find(query, None)
+        let mut options = FindOptions::default();
+        options.sort = Some(doc! { "priority_rank": -1, "_id": 1 });
+        let result = coll.find(query, Some(options))
             .await
             .map_err(mongo_error)?;
 
@@ -120,6 +591,216 @@ impl Model {
         Ok(notifications)
     }
 
+    /// Count the pending notifications of the sender profiles by priority.
+    pub async fn count_pending_notifications(&self, service_ids: &[ObjectId]) -> Result<Vec<(NotifyPriority, i64)>, Error> {
+        let coll = self.db.collection(COLLECTION_NOTIFY);
+        let pipeline = vec![
+            doc! { "$match": {
+                "sender_profile": { "$in": service_ids },
+                "status": bson::to_bson(&NotifyState::Pending).unwrap(),
+            } },
+            doc! { "$group": {
+                "_id": "$priority",
+                "count": { "$sum": 1 },
+            } },
+        ];
+        let result = coll.aggregate(pipeline, None)
+            .await
+            .map_err(mongo_error)?;
+
+        #[derive(Deserialize)]
+        struct PriorityCount {
+            /// `None` for the notifications queued before priority is introduced.
+            _id: Option<NotifyPriority>,
+            count: i64,
+        }
+        let counts: Vec<(NotifyPriority, i64)> = result
+            .filter_map(|doc| doc.ok().and_then(|d| bson::from_document::<PriorityCount>(d).ok()))
+            .map(|count| (count._id.unwrap_or_default(), count.count))
+            .collect()
+            .await;
+
+        Ok(counts)
+    }
+
+    /// Statistics of the notifications of the sender profiles queued in `[since, until)`,
+    /// computed by a single aggregation.
+    pub async fn notification_statistics(&self, service_ids: &[ObjectId], since: Option<SystemTime>, until: Option<SystemTime>) -> Result<NotifyStatistics, Error> {
+        let coll = self.db.collection(COLLECTION_NOTIFY);
+        let mut query = doc! {
+            "sender_profile": { "$in": service_ids },
+        };
+        let mut id_range = bson::Document::new();
+        if let Some(since) = since {
+            id_range.insert("$gte", object_id_since(since));
+        }
+        if let Some(until) = until {
+            id_range.insert("$lt", object_id_since(until));
+        }
+        if !id_range.is_empty() {
+            query.insert("_id", id_range);
+        }
+
+        let pipeline = vec![
+            doc! { "$match": query.clone() },
+            doc! { "$facet": {
+                // `Pending`, `Sent` and `Suppressed` are stored as string,
+                // an error as `{ "Error": [pub, inner] }` and a bounce as `{ "Bounced": detail }`.
+                "status": [
+                    { "$group": {
+                        "_id": { "$cond": [
+                            { "$eq": [{ "$type": "$status" }, "string"] },
+                            "$status",
+                            { "$cond": [{ "$eq": [{ "$type": "$status.Bounced" }, "missing"] }, "Error", "Bounced"] },
+                        ] },
+                        "count": { "$sum": 1 },
+                    } },
+                ],
+                "time_to_send": [
+                    { "$match": sent_query() },
+                    { "$project": { "duration": time_to_send() } },
+                    { "$group": {
+                        "_id": bson::Bson::Null,
+                        "mean": { "$avg": "$duration" },
+                        "count": { "$sum": 1 },
+                    } },
+                ],
+                "errors": [
+                    { "$match": { "status.Error": { "$exists": true } } },
+                    { "$group": {
+                        "_id": { "$arrayElemAt": ["$status.Error", 0] },
+                        "count": { "$sum": 1 },
+                    } },
+                    { "$sort": { "count": -1, "_id": 1 } },
+                    { "$limit": STATS_TOP_ENTRIES },
+                ],
+                "domains": [
+                    { "$project": {
+                        "recipients": { "$setUnion": [{ "$concatArrays": [
+                            // `to` of the notifications queued long ago may be a single address.
+                            { "$cond": [{ "$isArray": "$mail.to" }, "$mail.to", ["$mail.to"]] },
+                            { "$ifNull": ["$mail.cc", []] },
+                            { "$ifNull": ["$mail.bcc", []] },
+                        ] }] },
+                    } },
+                    { "$unwind": "$recipients" },
+                    { "$group": {
+                        "_id": { "$toLower": { "$trim": {
+                            "input": { "$arrayElemAt": [{ "$split": ["$recipients", "@"] }, -1] },
+                            "chars": "> ",
+                        } } },
+                        "count": { "$sum": 1 },
+                    } },
+                    { "$sort": { "count": -1, "_id": 1 } },
+                    { "$limit": STATS_TOP_ENTRIES },
+                ],
+                "tracking": [
+                    { "$match": { "tracked": true } },
+                    { "$group": {
+                        "_id": bson::Bson::Null,
+                        "tracked": { "$sum": 1 },
+                        // A click without loading the pixel, e.g. the images are blocked, is also an open.
+                        "opened": { "$sum": { "$cond": [{ "$or": [{ "$gt": ["$opens", 0] }, { "$gt": ["$clicks", 0] }] }, 1, 0] } },
+                        "clicked": { "$sum": { "$cond": [{ "$gt": ["$clicks", 0] }, 1, 0] } },
+                    } },
+                ],
+            } },
+        ];
+        let mut result = coll.aggregate(pipeline, None)
+            .await
+            .map_err(mongo_error)?;
+
+        #[derive(Deserialize)]
+        struct KeyCount {
+            _id: Option<String>,
+            count: i64,
+        }
+        #[derive(Deserialize)]
+        struct TimeToSend {
+            mean: Option<f64>,
+            count: i64,
+        }
+        #[derive(Deserialize)]
+        struct Tracking {
+            tracked: i64,
+            opened: i64,
+            clicked: i64,
+        }
+        #[derive(Deserialize)]
+        struct Facets {
+            status: Vec<KeyCount>,
+            time_to_send: Vec<TimeToSend>,
+            errors: Vec<KeyCount>,
+            domains: Vec<KeyCount>,
+            tracking: Vec<Tracking>,
+        }
+        let facets: Facets = match result.next().await {
+            Some(doc) => bson::from_document(doc.map_err(mongo_error)?)?,
+            None => return Ok(NotifyStatistics::default()),
+        };
+
+        let mut stats = NotifyStatistics::default();
+        for count in facets.status {
+            match count._id.as_deref() {
+                Some("Pending") => stats.pending += count.count,
+                Some("Sent") => stats.sent += count.count,
+                Some("Suppressed") => stats.suppressed += count.count,
+                Some("Bounced") => stats.bounced += count.count,
+                _ => stats.error += count.count,
+            }
+        }
+        if let Some(time) = facets.time_to_send.into_iter().next() {
+            stats.mean_time_to_send = time.mean;
+            if time.count > 0 {
+                let index = ((time.count - 1) as f64 * 0.95).floor() as i64;
+                stats.p95_time_to_send = self.nth_time_to_send(query, index, time.count).await?;
+            }
+        }
+        let top = |counts: Vec<KeyCount>| -> Vec<(String, i64)> {
+            counts.into_iter()
+                .filter_map(|count| count._id.map(|key| (key, count.count)))
+                .collect()
+        };
+        stats.top_errors = top(facets.errors);
+        stats.top_domains = top(facets.domains);
+        if let Some(tracking) = facets.tracking.into_iter().next() {
+            stats.tracked = tracking.tracked;
+            stats.opened = tracking.opened;
+            stats.clicked = tracking.clicked;
+        }
+
+        Ok(stats)
+    }
+
+    /// Milliseconds from queued to sent of the `index`th fastest of the `count` sent notifications matching `query`.
+    async fn nth_time_to_send(&self, query: bson::Document, index: i64, count: i64) -> Result<Option<i64>, Error> {
+        let coll = self.db.collection(COLLECTION_NOTIFY);
+        // Counted from the slowest, so the sort only keeps the slowest ones instead of most of the durations.
+        let skip = count - 1 - index;
+        let pipeline = vec![
+            doc! { "$match": query },
+            doc! { "$match": sent_query() },
+            doc! { "$project": { "_id": 0, "duration": time_to_send() } },
+            doc! { "$sort": { "duration": -1 } },
+            doc! { "$skip": skip },
+            doc! { "$limit": 1 },
+        ];
+        let mut options = AggregateOptions::default();
+        options.allow_disk_use = Some(true);
+        let mut result = coll.aggregate(pipeline, Some(options))
+            .await
+            .map_err(mongo_error)?;
+
+        #[derive(Deserialize)]
+        struct TimeToSend {
+            duration: i64,
+        }
+        match result.next().await {
+            Some(doc) => Ok(Some(bson::from_document::<TimeToSend>(doc.map_err(mongo_error)?)?.duration)),
+            None => Ok(None),
+        }
+    }
+
     pub async fn get_notification_by_message_id(&self, message_id: &ObjectId) -> Result<EmailNotify, Error> {
         let coll = self.db.collection(COLLECTION_NOTIFY);
         let query = doc! {
@@ -135,6 +816,20 @@ impl Model {
         Ok(notify)
     }
 
+    /// Find the notification by the `Message-ID` of its mail, e.g. referred by a delivery status notification.
+    pub async fn get_notification_by_mail_id(&self, mail_message_id: &str) -> Result<EmailNotify, Error> {
+        let coll = self.db.collection(COLLECTION_NOTIFY);
+        let query = doc! {
+            "message_id": mail_message_id,
+        };
+        let doc = coll.find_one(query, None)
+            .await
+            .map_err(mongo_error)?
+            .ok_or(Error::NoRecord)?;
+
+        Ok(bson::from_document(doc).map_err(Error::from)?)
+    }
+
     pub async fn add_notification(&self, notify: &EmailNotify) -> Result<(), Error> {
         let coll = self.db.collection(COLLECTION_NOTIFY);
         let doc = bson::to_document(notify).map_err(Error::from)?;
@@ -142,13 +837,287 @@ impl Model {
         Ok(())
     }
 
+    /// Insert the notifications, return the index of each one not inserted
+    /// since its idempotency key has been queued by the same sender profile within `IDEMPOTENCY_WINDOW`,
+    /// with the previous notification.
+    pub async fn add_notifications(&self, notifications: &[EmailNotify]) -> Result<Vec<(usize, EmailNotify)>, Error> {
+        let mut docs = notifications
+            .iter()
+            .map(bson::to_document)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::from)?;
+
+        let mut repeated = Vec::new();
+        let mut inserting = Vec::with_capacity(notifications.len());
+        for (idx, notify) in notifications.iter().enumerate() {
+            match self.claim_or_get_previous(notify).await {
+                Ok(Some(previous)) => repeated.push((idx, previous)),
+                Ok(None) => inserting.push(notify),
+                Err(err) => {
+                    let claimed: Vec<ObjectId> = inserting.iter().map(|notify| notify._id.clone()).collect();
+                    self.release_idempotency_keys(&claimed).await?;
+                    return Err(err);
+                }
+            }
+        }
+        for (idx, _) in repeated.iter().rev() {
+            docs.remove(*idx);
+        }
+        if docs.is_empty() {
+            return Ok(repeated);
+        }
+
+        let coll = self.db.collection(COLLECTION_NOTIFY);
+        let mut options = InsertManyOptions::default();
+        options.ordered = Some(false);
+        if let Err(err) = coll.insert_many(docs, Some(options)).await {
+            // Release the keys of the notifications not inserted, so that the request can be retried.
+            let failed: Vec<ObjectId> = match failed_write_indices(&err) {
+                Some(indices) => indices.into_iter().map(|idx| inserting[idx]._id.clone()).collect(),
+                None => inserting.iter().map(|notify| notify._id.clone()).collect(),
+            };
+            self.release_idempotency_keys(&failed).await?;
+            return Err(Error::from(err));
+        }
+        Ok(repeated)
+    }
+
+    /// Claim the idempotency key of the notification if it has one,
+    /// or the previous notification holding the claim.
+    async fn claim_or_get_previous(&self, notify: &EmailNotify) -> Result<Option<EmailNotify>, Error> {
+        match &notify.idempotency_key {
+            Some(key) if !self.claim_idempotency_key(notify, key).await? => {
+                self.get_claimed_notification(&notify.sender_profile, key).await.map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Claim the idempotency key for the notification, false if the key has been claimed
+    /// by the same sender profile within `IDEMPOTENCY_WINDOW`.
+    ///
+    /// An expired claim is taken over, since the TTL monitor may remove it a while after the window.
+    async fn claim_idempotency_key(&self, notify: &EmailNotify, key: &str) -> Result<bool, Error> {
+        let coll = self.db.collection(COLLECTION_IDEMPOTENCY_KEY);
+        let now = Utc::now();
+        let query = doc! {
+            "_id": { "sender_profile": &notify.sender_profile, "key": key },
+            "claimed_at": {
+                "$lt": now - chrono::Duration::seconds(IDEMPOTENCY_WINDOW.as_secs() as i64),
+            },
+        };
+        let update = doc! {
+            "$set": {
+                "notify_id": &notify._id,
+                "claimed_at": now,
+            },
+        };
+        let mut options = UpdateOptions::default();
+        options.upsert = Some(true);
+        match coll.update_one(query, update, Some(options)).await {
+            Ok(_) => Ok(true),
+            // Claimed within the window, the upsert collides with the existing claim.
+            Err(err) if is_duplicate_key(&err) => Ok(false),
+            Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Remove the claims of the idempotency keys held by the notifications.
+    async fn release_idempotency_keys(&self, notify_ids: &[ObjectId]) -> Result<(), Error> {
+        let coll = self.db.collection(COLLECTION_IDEMPOTENCY_KEY);
+        coll.delete_many(doc! { "notify_id": { "$in": notify_ids } }, None)
+            .await
+            .map_err(mongo_error)?;
+        Ok(())
+    }
+
+    /// The notification holding the claim of the idempotency key of the sender profile.
+    ///
+    /// The claim is made right before the notification is inserted,
+    /// wait a moment for a notification being queued by a concurrent request.
+    async fn get_claimed_notification(&self, service_id: &ObjectId, key: &str) -> Result<EmailNotify, Error> {
+        let claims = self.db.collection(COLLECTION_IDEMPOTENCY_KEY);
+        let claim = claims.find_one(doc! { "_id": { "sender_profile": service_id, "key": key } }, None)
+            .await
+            .map_err(mongo_error)?
+            .ok_or(Error::NoRecord)?;
+        let notify_id = claim.get_object_id("notify_id").map_err(doc_error)?;
+
+        let coll = self.db.collection(COLLECTION_NOTIFY);
+        for _ in 0..CLAIM_WAIT_ATTEMPTS {
+            if let Some(doc) = coll.find_one(doc! { "_id": notify_id }, None).await.map_err(mongo_error)? {
+                return Ok(bson::from_document(doc).map_err(Error::from)?);
+            }
+            tokio::time::delay_for(CLAIM_WAIT_INTERVAL).await;
+        }
+        Err(Error::NoRecord)
+    }
+
+    /// Notifications queued by any of the sender profiles within `IDEMPOTENCY_WINDOW` with any of the idempotency keys.
+    pub async fn get_notifications_by_idempotency_keys(&self, service_ids: &[ObjectId], keys: &[String]) -> Result<Vec<EmailNotify>, Error> {
+        let coll = self.db.collection(COLLECTION_NOTIFY);
+        let query = doc! {
+            "sender_profile": {
+                "$in": service_ids,
+            },
+            "idempotency_key": {
+                "$in": keys,
+            },
+            "_id": {
+                "$gte": object_id_since(SystemTime::now() - IDEMPOTENCY_WINDOW),
+            },
+        };
+        let result = coll.find(query, None)
+            .await
+            .map_err(mongo_error)?;
+        let notifications: Vec<EmailNotify> = result
+            .filter_map(|doc| doc.ok().and_then(|d| bson::from_document(d).ok()))
+            .collect()
+            .await;
+
+        Ok(notifications)
+    }
+
+    /// Insert the notification unless another one with the same idempotency key
+    /// has been queued by the same sender profile within `IDEMPOTENCY_WINDOW`.
+    ///
+    /// Return the previous notification if there is one.
+    pub async fn add_notification_idempotent(&self, notify: &EmailNotify) -> Result<Option<EmailNotify>, Error> {
+        if let Some(previous) = self.claim_or_get_previous(notify).await? {
+            return Ok(Some(previous));
+        }
+        if let Err(err) = self.add_notification(notify).await {
+            self.release_idempotency_keys(&[notify._id.clone()]).await?;
+            return Err(err);
+        }
+        Ok(None)
+    }
+
+    /// Strip the body and attachments of the notifications of the sender profiles queued before `time`,
+    /// except the pending ones. Return the number of notifications stripped.
+    pub async fn purge_notification_bodies(&self, service_ids: &[ObjectId], before: SystemTime) -> Result<i64, Error> {
+        let coll = self.db.collection(COLLECTION_NOTIFY);
+        let query = doc! {
+            "sender_profile": {
+                "$in": service_ids,
+            },
+            "_id": {
+                "$lt": object_id_since(before),
+            },
+            "status": {
+                "$ne": bson::to_bson(&NotifyState::Pending).unwrap(),
+            },
+            "body_purged": {
+                "$ne": true,
+            },
+        };
+        let update = doc! {
+            "$set": {
+                "mail.body": "",
+                "mail.html": bson::Bson::Null,
+                "mail.text": bson::Bson::Null,
+                "mail.attachments": [],
+                "body_purged": true,
+            },
+        };
+        let result = coll.update_many(query, update, None).await.map_err(mongo_error)?;
+        Ok(result.modified_count)
+    }
+
+    /// Delete the notifications of the sender profiles queued before `time`, except the pending ones.
+    /// Return the number of notifications deleted.
+    pub async fn delete_notifications_before(&self, service_ids: &[ObjectId], before: SystemTime) -> Result<i64, Error> {
+        let coll = self.db.collection(COLLECTION_NOTIFY);
+        let query = doc! {
+            "sender_profile": {
+                "$in": service_ids,
+            },
+            "_id": {
+                "$lt": object_id_since(before),
+            },
+            "status": {
+                "$ne": bson::to_bson(&NotifyState::Pending).unwrap(),
+            },
+        };
+        let result = coll.delete_many(query, None).await.map_err(mongo_error)?;
+        Ok(result.deleted_count)
+    }
+
+    /// Count an open of the notification, `NoRecord` if it's not tracked.
+    pub async fn record_notification_open(&self, message_id: &ObjectId) -> Result<(), Error> {
+        self.record_tracking(message_id, doc! { "$inc": { "opens": 1 } }, "first_opened_at").await
+    }
+
+    /// Count a click of the link in the notification, `NoRecord` if it's not tracked.
+    pub async fn record_notification_click(&self, message_id: &ObjectId, url: &str) -> Result<(), Error> {
+        let update = doc! {
+            "$inc": { "clicks": 1 },
+            "$addToSet": { "clicked_links": url },
+        };
+        self.record_tracking(message_id, update, "first_clicked_at").await
+    }
+
+    async fn record_tracking(&self, message_id: &ObjectId, update: bson::Document, first_time_field: &str) -> Result<(), Error> {
+        let coll = self.db.collection(COLLECTION_NOTIFY);
+        let query = doc! {
+            "_id": message_id,
+            "tracked": true,
+        };
+        let result = coll.update_one(query, update, None).await.map_err(mongo_error)?;
+        if result.matched_count <= 0 {
+            return Err(Error::NoRecord);
+        }
+
+        // Matches the notifications without the field as well.
+        let query = doc! {
+            "_id": message_id,
+            first_time_field: bson::Bson::Null,
+        };
+        let update = doc! {
+            "$set": { first_time_field: Utc::now() },
+        };
+        coll.update_one(query, update, None).await.map_err(mongo_error)?;
+        Ok(())
+    }
+
+    /// Store the status of the bounced recipients at `bounced` in `notify.recipients`,
+    /// and the status of the notification if it's changed by the bounce. The other fields are kept as stored.
+    pub async fn record_notification_bounce(&self, notify: &EmailNotify, bounced: &[usize], status_changed: bool) -> Result<(), Error> {
+        let coll = self.db.collection(COLLECTION_NOTIFY);
+        let query = doc! {
+            "_id": &notify._id,
+        };
+        let mut fields = bson::Document::new();
+        for &index in bounced {
+            fields.insert(format!("recipients.{}.status", index), bson::to_bson(&notify.recipients[index].status)?);
+        }
+        if status_changed {
+            fields.insert("status", bson::to_bson(&notify.status)?);
+        }
+        let update = doc! {
+            "$set": fields,
+        };
+        let result = coll.update_one(query, update, None).await.map_err(mongo_error)?;
+        if result.matched_count <= 0 {
+            Err(Error::NoRecord)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Store the notification, except the opens and clicks which are only counted by `record_tracking`,
+    /// so that the ones recorded while the notification is being sent or bounced are kept.
     pub async fn update_notification(&self, notify: &EmailNotify) -> Result<(), Error> {
         let coll = self.db.collection(COLLECTION_NOTIFY);
         let query = doc! {
             "_id": &notify._id
         };
+        let mut fields = bson::to_document(notify)?;
+        for field in &["opens", "clicks", "first_opened_at", "first_clicked_at", "clicked_links"] {
+            fields.remove(field);
+        }
         let update = doc!{
-            "$set": bson::to_bson(notify).unwrap(),
+            "$set": fields,
         };
         let result = coll.update_one(query, update, None).await.map_err(Error::from)?;
         if result.matched_count <= 0 {
@@ -157,4 +1126,27 @@ impl Model {
             Ok(())
         }
     }
-}
\ No newline at end of file
+}
+
+/// The sent notifications, in the statistics of the time to send.
+fn sent_query() -> bson::Document {
+    doc! {
+        "status": bson::to_bson(&NotifyState::Sent).unwrap(),
+        "sent_at": { "$type": "date" },
+    }
+}
+
+/// Milliseconds from queued to sent, the notifications queued long ago have no `created_at`.
+fn time_to_send() -> bson::Document {
+    doc! { "$subtract": ["$sent_at", { "$ifNull": ["$created_at", { "$toDate": "$_id" }] }] }
+}
+
+/// The smallest `ObjectId` generated at or after the given time.
+fn object_id_since(time: SystemTime) -> ObjectId {
+    let timestamp = time.duration_since(UNIX_EPOCH)
+        .map(|t| t.as_secs() as u32)
+        .unwrap_or(0);
+    let mut bytes = [0u8; 12];
+    bytes[..4].copy_from_slice(&timestamp.to_be_bytes());
+    ObjectId::with_bytes(bytes)
+}
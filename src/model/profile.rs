@@ -1,9 +1,11 @@
 
 use mongodb::{
-    bson::{ doc, oid::ObjectId, },
+    bson::{ Bson, Document, doc, oid::ObjectId, },
 };
 use serde::{Serialize, Deserialize};
 
+use crate::utils::variant_eq;
+
 #[derive(Serialize, Deserialize, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub enum Access {
     Root = 4,
@@ -52,6 +54,35 @@ impl Service {
             Service::ServiceManagement(_) => "ServiceManagement",
         }
     }
+
+    /// A user can have only one service of each type,
+    /// except `EmailNotify` services which are distinguished by the sender name.
+    pub fn conflicts_with(&self, other: &Service) -> bool {
+        match (self, other) {
+            (Service::EmailNotify(a), Service::EmailNotify(b)) => a.sender == b.sender,
+            (a, b) => variant_eq(a, b),
+        }
+    }
+
+    /// Matches the service records which conflict with this service.
+    pub(super) fn conflict_query(&self) -> Document {
+        match self {
+            Service::EmailNotify(profile) => {
+                // `null` also matches the profiles without `sender` field.
+                let sender = match &profile.sender {
+                    Some(sender) => Bson::String(sender.clone()),
+                    None => Bson::Null,
+                };
+                doc! {
+                    "service.type": self.type_name(),
+                    "service.profile.sender": sender,
+                }
+            }
+            _ => doc! {
+                "service.type": self.type_name(),
+            },
+        }
+    }
 }
 
 pub trait ValidateProfile {
@@ -14,6 +14,7 @@ use mongodb::{
     }
 };
 use serde::{Serialize, Deserialize};
+use tokio::stream::StreamExt;
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct ServiceManagerProfile {
@@ -62,9 +63,7 @@ impl Model {
             "uid": id,
             "services": {
                 "$not": {
-                    "$elemMatch": {
-                        "service.type": service.type_name()
-                    }
+                    "$elemMatch": service.conflict_query()
                 }
             }
         };
@@ -126,6 +125,26 @@ impl Model {
         Ok(service.service)
     }
 
+    /// All the service records of the type of all users.
+    pub async fn get_services_by_type(&self, type_name: &str) -> Result<Vec<ServiceRecord>, Error> {
+        let coll = self.db.collection(COLLECTION_PROFILE);
+        let query = doc! {
+            "services.service.type": type_name,
+        };
+        let profiles: Vec<UserProfile> = coll.find(query, None)
+            .await
+            .map_err(mongo_error)?
+            .filter_map(|doc| doc.ok().and_then(|d| bson::from_document(d).ok()))
+            .collect()
+            .await;
+
+        let services = profiles.into_iter()
+            .flat_map(|profile| profile.services)
+            .filter(|record| record.service.type_name() == type_name)
+            .collect();
+        Ok(services)
+    }
+
     pub async fn get_service_owner(&self, service_id: &ObjectId) -> Result<UserProfile, Error> {
         let coll = self.db.collection(COLLECTION_PROFILE);
         let query = doc! {
@@ -0,0 +1,140 @@
+use chrono::Utc;
+use mongodb::{
+    bson::{self, doc, oid::ObjectId},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+};
+use serde::{Serialize, Deserialize};
+use tokio::stream::StreamExt;
+
+use super::{Error, Model, error::mongo_error};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum SuppressionReason {
+    /// Added through the API.
+    Manual,
+    /// The recipient unsubscribed by the link in the notification.
+    Unsubscribed,
+    /// The recipient address is permanently rejected.
+    HardBounce,
+}
+
+/// A recipient address which the notifications of a sender profile are not sent to.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Suppression {
+    pub _id: ObjectId,
+    /// Id of the `EmailNotify` service record owns this entry.
+    pub sender_profile: ObjectId,
+    /// In lowercase, unique among the entries of a sender profile.
+    pub address: String,
+    pub reason: SuppressionReason,
+    /// The note of a manual entry, or the reply of a hard bounce.
+    #[serde(default)]
+    pub detail: Option<String>,
+    pub created_at: bson::DateTime,
+}
+
+const COLLECTION_SUPPRESSION: &str = "suppression";
+
+impl Model {
+    pub(super) async fn create_suppression_indexes(&self) -> Result<(), Error> {
+        let command = doc! {
+            "createIndexes": COLLECTION_SUPPRESSION,
+            "indexes": [
+                {
+                    "key": { "sender_profile": 1, "address": 1 },
+                    "name": "sender_profile_address",
+                    "unique": true,
+                },
+            ],
+        };
+        self.db.run_command(command, None).await.map_err(mongo_error)?;
+        Ok(())
+    }
+
+    /// Add the address to the suppression list of the sender profile,
+    /// the reason and detail of an existing entry are replaced.
+    pub async fn add_suppression(&self, sender_profile: &ObjectId, address: &str, reason: SuppressionReason, detail: Option<String>) -> Result<Suppression, Error> {
+        let coll = self.db.collection(COLLECTION_SUPPRESSION);
+        let query = doc! {
+            "sender_profile": sender_profile,
+            "address": address.to_lowercase(),
+        };
+        let update = doc! {
+            "$set": {
+                "reason": bson::to_bson(&reason).map_err(Error::from)?,
+                "detail": bson::to_bson(&detail).map_err(Error::from)?,
+            },
+            "$setOnInsert": {
+                "_id": ObjectId::new(),
+                "created_at": Utc::now(),
+            },
+        };
+        let mut options = FindOneAndUpdateOptions::default();
+        options.upsert = Some(true);
+        options.return_document = Some(ReturnDocument::After);
+
+        let doc = coll.find_one_and_update(query, update, Some(options))
+            .await
+            .map_err(mongo_error)?
+            .ok_or(Error::NoRecord)?;
+
+        Ok(bson::from_document(doc).map_err(Error::from)?)
+    }
+
+    pub async fn remove_suppression(&self, sender_profile: &ObjectId, address: &str) -> Result<(), Error> {
+        let coll = self.db.collection(COLLECTION_SUPPRESSION);
+        let query = doc! {
+            "sender_profile": sender_profile,
+            "address": address.to_lowercase(),
+        };
+        let result = coll.delete_one(query, None).await.map_err(mongo_error)?;
+        if result.deleted_count <= 0 {
+            Err(Error::NoRecord)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The suppression list of the sender profile, the latest added first.
+    pub async fn get_suppressions(&self, sender_profile: &ObjectId) -> Result<Vec<Suppression>, Error> {
+        let coll = self.db.collection(COLLECTION_SUPPRESSION);
+        let query = doc! {
+            "sender_profile": sender_profile,
+        };
+        let mut options = FindOptions::default();
+        options.sort = Some(doc! { "_id": -1 });
+
+        let result = coll.find(query, Some(options))
+            .await
+            .map_err(mongo_error)?;
+        let suppressions: Vec<Suppression> = result
+            .filter_map(|doc| doc.ok().and_then(|d| bson::from_document(d).ok()))
+            .collect()
+            .await;
+
+        Ok(suppressions)
+    }
+
+    /// The entries of any of `addresses` in the suppression lists of the sender profiles, case-insensitive.
+    pub async fn find_suppressions(&self, sender_profiles: &[ObjectId], addresses: &[&str]) -> Result<Vec<Suppression>, Error> {
+        if addresses.is_empty() {
+            return Ok(Vec::new());
+        }
+        let coll = self.db.collection(COLLECTION_SUPPRESSION);
+        let addresses: Vec<String> = addresses.iter().map(|addr| addr.to_lowercase()).collect();
+        let query = doc! {
+            "sender_profile": { "$in": sender_profiles },
+            "address": { "$in": addresses },
+        };
+
+        let result = coll.find(query, None)
+            .await
+            .map_err(mongo_error)?;
+        let suppressions: Vec<Suppression> = result
+            .filter_map(|doc| doc.ok().and_then(|d| bson::from_document(d).ok()))
+            .collect()
+            .await;
+
+        Ok(suppressions)
+    }
+}
@@ -0,0 +1,146 @@
+use mongodb::{
+    bson::{self, Bson, doc, oid::ObjectId},
+    options::FindOneOptions,
+};
+use serde::{Serialize, Deserialize};
+use tokio::stream::StreamExt;
+
+use super::{Error, Model, error::mongo_error};
+
+#[derive(Serialize, Deserialize)]
+pub struct NotifyTemplate {
+    pub _id: ObjectId,
+    /// Id of the `EmailNotify` service record owns this template.
+    pub service_id: ObjectId,
+    pub name: String,
+    /// Language tag of this variant, `None` for the default variant.
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// Versions are counted separately for each locale variant.
+    pub version: i32,
+    pub subject: String,
+    pub html: Option<String>,
+    pub text: Option<String>,
+}
+
+/// The template variant and its version used to render a notification.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TemplateRef {
+    pub name: String,
+    #[serde(default)]
+    pub locale: Option<String>,
+    pub version: i32,
+}
+
+const COLLECTION_TEMPLATE: &str = "template";
+
+impl Model {
+    /// Get the specific version of a template variant, or the latest version if `version` is `None`.
+    pub async fn get_template(&self, service_id: &ObjectId, name: &str, locale: Option<&str>, version: Option<i32>) -> Result<NotifyTemplate, Error> {
+        let coll = self.db.collection(COLLECTION_TEMPLATE);
+        let mut query = doc! {
+            "service_id": service_id,
+            "name": name,
+            "locale": locale_bson(locale),
+        };
+        if let Some(version) = version {
+            query.insert("version", version);
+        }
+        let mut options = FindOneOptions::default();
+        options.sort = Some(doc! { "version": -1 });
+
+        let doc = coll.find_one(query, Some(options))
+            .await
+            .map_err(mongo_error)?
+            .ok_or(Error::NoRecord)?;
+
+        Ok(bson::from_document(doc).map_err(Error::from)?)
+    }
+
+    /// Get the first existing variant in the order of `locales`, then the default variant.
+    pub async fn get_localized_template(&self, service_id: &ObjectId, name: &str, locales: &[&str], version: Option<i32>) -> Result<NotifyTemplate, Error> {
+        let candidates = locales.iter().map(|locale| Some(*locale)).chain(Some(None));
+        for locale in candidates {
+            match self.get_template(service_id, name, locale, version).await {
+                Err(Error::NoRecord) => continue,
+                result => return result,
+            }
+        }
+        Err(Error::NoRecord)
+    }
+
+    /// Get the latest version of all template variants of a service.
+    pub async fn get_templates(&self, service_id: &ObjectId) -> Result<Vec<NotifyTemplate>, Error> {
+        let coll = self.db.collection(COLLECTION_TEMPLATE);
+        let pipeline = vec![
+            doc! { "$match": { "service_id": service_id } },
+            doc! { "$sort": { "name": 1, "locale": 1, "version": -1 } },
+            doc! { "$group": {
+                "_id": { "name": "$name", "locale": "$locale" },
+                "template": { "$first": "$$ROOT" },
+            } },
+            doc! { "$replaceRoot": { "newRoot": "$template" } },
+            doc! { "$sort": { "name": 1, "locale": 1 } },
+        ];
+        let result = coll.aggregate(pipeline, None)
+            .await
+            .map_err(mongo_error)?;
+        let templates: Vec<NotifyTemplate> = result
+            .filter_map(|doc| doc.ok().and_then(|d| bson::from_document(d).ok()))
+            .collect()
+            .await;
+
+        Ok(templates)
+    }
+
+    /// Save the template variant as a new version after the latest one.
+    pub async fn add_template_version(&self, service_id: &ObjectId, name: &str, locale: Option<&str>, subject: String, html: Option<String>, text: Option<String>) -> Result<NotifyTemplate, Error> {
+        let version = match self.get_template(service_id, name, locale, None).await {
+            Ok(latest) => latest.version + 1,
+            Err(Error::NoRecord) => 1,
+            Err(err) => return Err(err),
+        };
+        let template = NotifyTemplate {
+            _id: ObjectId::new(),
+            service_id: service_id.clone(),
+            name: name.to_string(),
+            locale: locale.map(|locale| locale.to_string()),
+            version,
+            subject,
+            html,
+            text,
+        };
+
+        let coll = self.db.collection(COLLECTION_TEMPLATE);
+        let doc = bson::to_document(&template).map_err(Error::from)?;
+        coll.insert_one(doc, None).await.map_err(mongo_error)?;
+
+        Ok(template)
+    }
+
+    /// Remove all versions of a template variant, or all variants if `locale` is `None`.
+    pub async fn remove_template(&self, service_id: &ObjectId, name: &str, locale: Option<&str>) -> Result<(), Error> {
+        let coll = self.db.collection(COLLECTION_TEMPLATE);
+        let mut query = doc! {
+            "service_id": service_id,
+            "name": name,
+        };
+        if let Some(locale) = locale {
+            query.insert("locale", locale);
+        }
+        let result = coll.delete_many(query, None).await.map_err(mongo_error)?;
+        if result.deleted_count <= 0 {
+            Err(Error::NoRecord)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// `null` also matches the documents without `locale` field.
+fn locale_bson(locale: Option<&str>) -> Bson {
+    match locale {
+        Some(locale) => Bson::String(locale.to_string()),
+        None => Bson::Null,
+    }
+}
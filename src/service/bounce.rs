@@ -0,0 +1,524 @@
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use mongodb::bson::oid::ObjectId;
+use openssl::{error::ErrorStack, memcmp};
+use tokio::{io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader}, net::{TcpListener, TcpStream}, sync::{Semaphore, broadcast}, time::timeout};
+
+use crate::model::{self, Model, NotifyState, SuppressionReason};
+
+use super::{EmailNotifyService, email_notify::{publish_event, sender_profile}, signature::sign, webhook::{NotifyEvent, WebhookSender}};
+
+/// Inbound mails larger than this are rejected, a bounce usually returns only the headers of the notification.
+const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+/// Longer than the 1000 of RFC 5321 to tolerate the sloppy senders, the session is closed on a longer line.
+const MAX_LINE_LEN: usize = 8 * 1024;
+/// The session is closed if no line is received in time, https://tools.ietf.org/html/rfc5321#section-4.5.3.2
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Time to receive the whole mail data.
+const DATA_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// The sessions over it are refused until some are closed.
+const MAX_SESSIONS: usize = 100;
+/// Local part prefix of the VERP envelope senders.
+const VERP_PREFIX: &str = "bounce+";
+/// Bytes of the HMAC kept in the VERP address, the local part is limited to 64 characters.
+const VERP_SIGNATURE_LEN: usize = 8;
+
+/// Signs and verifies the VERP envelope senders, so that the bounce of a notification
+/// cannot be forged by guessing its `message_id`.
+#[derive(Clone)]
+pub struct VerpAddresses {
+    secret: String,
+}
+
+impl VerpAddresses {
+    pub fn new(secret: &str) -> Self {
+        Self {
+            secret: secret.to_string(),
+        }
+    }
+
+    /// The VERP envelope sender of the notification, `bounce+{message_id}.{signature}@{domain}`.
+    pub(super) fn address(&self, message_id: &ObjectId, domain: &str) -> Result<String, ErrorStack> {
+        let signature = self.signature(&message_id.to_hex())?;
+        Ok(format!("{}{}.{}@{}", VERP_PREFIX, message_id.to_hex(), hex::encode(signature), domain))
+    }
+
+    /// The `message_id` of the notification encoded in a VERP envelope sender with a valid signature.
+    fn message_id(&self, address: &str) -> Option<ObjectId> {
+        let local_part = &address[..address.rfind('@')?];
+        let payload = strip_verp_prefix(local_part)?;
+        let idx = payload.rfind('.')?;
+        let (message_id, signature) = (&payload[..idx], &payload[idx + 1..]);
+        let signature = hex::decode(signature).ok()?;
+        let expected = self.signature(&message_id.to_lowercase()).ok()?;
+        if signature.len() != expected.len() || !memcmp::eq(&signature, &expected) {
+            return None;
+        }
+        ObjectId::with_string(message_id).ok()
+    }
+
+    fn signature(&self, message_id: &str) -> Result<Vec<u8>, ErrorStack> {
+        let mut signature = sign(&self.secret, &format!("verp.{}", message_id))?;
+        signature.truncate(VERP_SIGNATURE_LEN);
+        Ok(signature)
+    }
+}
+
+fn strip_verp_prefix(local_part: &str) -> Option<&str> {
+    local_part.get(..VERP_PREFIX.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(VERP_PREFIX))
+        .map(|_| &local_part[VERP_PREFIX.len()..])
+}
+
+/// The address looks like a VERP envelope sender, whether or not it's correctly signed.
+fn is_verp_address(address: &str) -> bool {
+    address.rfind('@').map_or(false, |idx| strip_verp_prefix(&address[..idx]).is_some())
+}
+
+/// Enhanced status code `class.subject.detail`, https://tools.ietf.org/html/rfc3463
+pub(super) fn is_enhanced_code(code: &str) -> bool {
+    code.split('.').count() == 3 && code.split('.').all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// The enhanced status code `5.1.x` is a permanent failure of the recipient address itself.
+pub(super) fn is_address_failure(code: &str) -> bool {
+    code.starts_with("5.1.")
+}
+
+/// A per-recipient field group of a delivery status notification, https://tools.ietf.org/html/rfc3464
+#[derive(Debug)]
+struct RecipientReport {
+    /// The `Original-Recipient` as sent in `RCPT TO`, or the `Final-Recipient` if absent.
+    address: String,
+    /// `failed`, `delayed`, `delivered`, `relayed` or `expanded`.
+    action: String,
+    /// The enhanced status code, e.g. `5.1.1`.
+    status: String,
+    diagnostic: Option<String>,
+}
+
+impl RecipientReport {
+    fn is_failed(&self) -> bool {
+        self.action == "failed"
+    }
+
+    /// The status with the diagnostic, e.g. `5.1.1 smtp; 550 5.1.1 No such user`.
+    fn detail(&self) -> String {
+        match &self.diagnostic {
+            Some(diagnostic) => format!("{} {}", self.status, diagnostic),
+            None => self.status.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct DeliveryReport {
+    /// `Message-ID` of the returned mail without the angle brackets.
+    message_id: Option<String>,
+    recipients: Vec<RecipientReport>,
+}
+
+/// Parse a delivery status notification, `None` if the mail has no delivery status.
+fn parse_report(data: &str) -> Option<DeliveryReport> {
+    let data = data.replace("\r\n", "\n");
+    let mut report = DeliveryReport::default();
+    parse_entity(&data, &mut report);
+    if report.recipients.is_empty() {
+        None
+    } else {
+        Some(report)
+    }
+}
+
+/// Look for the delivery status and the returned headers in the MIME entity and its parts.
+fn parse_entity(entity: &str, report: &mut DeliveryReport) {
+    let (header, body) = split_header(entity);
+    let fields = parse_fields(header);
+    let content_type = field(&fields, "content-type").unwrap_or("text/plain");
+    let mime_type = content_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+    match mime_type.as_str() {
+        "message/delivery-status" | "message/global-delivery-status" => parse_delivery_status(body, report),
+        "message/rfc822" | "message/global" | "text/rfc822-headers" | "message/global-headers" => {
+            if report.message_id.is_none() {
+                let returned = parse_fields(split_header(body).0);
+                report.message_id = field(&returned, "message-id")
+                    .map(|id| id.trim_start_matches('<').trim_end_matches('>').to_string());
+            }
+        }
+        _ if mime_type.starts_with("multipart/") => {
+            if let Some(boundary) = parameter(content_type, "boundary") {
+                for part in multipart_parts(body, &boundary) {
+                    parse_entity(part, report);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+/// The per-message fields come first, followed by a field group for each recipient, separated by blank lines.
+fn parse_delivery_status(body: &str, report: &mut DeliveryReport) {
+    for group in body.split("\n\n") {
+        let fields = parse_fields(group);
+        let address = field(&fields, "original-recipient").or_else(|| field(&fields, "final-recipient"));
+        if let Some(address) = address {
+            report.recipients.push(RecipientReport {
+                address: recipient_address(address),
+                action: field(&fields, "action").unwrap_or_default().to_lowercase(),
+                status: field(&fields, "status")
+                    .and_then(|status| status.split_whitespace().next())
+                    .unwrap_or_default()
+                    .to_string(),
+                diagnostic: field(&fields, "diagnostic-code").map(|diagnostic| diagnostic.to_string()),
+            });
+        }
+    }
+}
+
+/// `rfc822; user@example.com` without the address type.
+fn recipient_address(value: &str) -> String {
+    let address = value.find(';').map_or(value, |idx| &value[idx + 1..]);
+    address.trim().trim_start_matches('<').trim_end_matches('>').to_string()
+}
+
+/// The header and the body separated by the first blank line, lines are separated by `\n`.
+fn split_header(entity: &str) -> (&str, &str) {
+    if entity.starts_with('\n') {
+        return ("", &entity[1..]);
+    }
+    match entity.find("\n\n") {
+        Some(idx) => (&entity[..idx], &entity[idx + 2..]),
+        None => (entity, ""),
+    }
+}
+
+/// Header fields with the folded lines joined, the names are in lowercase.
+fn parse_fields(header: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in header.lines() {
+        if line.starts_with(|c| c == ' ' || c == '\t') {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some(idx) = line.find(':') {
+            fields.push((line[..idx].trim().to_lowercase(), line[idx + 1..].trim().to_string()));
+        }
+    }
+    fields
+}
+
+fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    fields.iter()
+        .find(|(field_name, _)| field_name == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Value of a parameter of the header field, e.g. `boundary` of `Content-Type`.
+fn parameter(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let idx = param.find('=')?;
+        if param[..idx].trim().eq_ignore_ascii_case(name) {
+            Some(param[idx + 1..].trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// The body parts between the delimiter lines of the boundary, without the preamble and the epilogue.
+fn multipart_parts<'a>(body: &'a str, boundary: &str) -> Vec<&'a str> {
+    let delimiter = format!("--{}", boundary);
+    let mut parts = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut offset = 0;
+    while offset < body.len() {
+        let end = body[offset..].find('\n').map_or(body.len(), |idx| offset + idx + 1);
+        let line = body[offset..end].trim_end();
+        if line.starts_with(delimiter.as_str()) {
+            // The line break before the delimiter belongs to the delimiter.
+            if let Some(start) = start {
+                parts.push(if offset > start { &body[start..offset - 1] } else { "" });
+            }
+            if line[delimiter.len()..].starts_with("--") {
+                break;
+            }
+            start = Some(end);
+        }
+        offset = end;
+    }
+    parts
+}
+
+/// The address in `MAIL FROM:<path>` or `RCPT TO:<path>`.
+fn path(command: &str) -> String {
+    match (command.find('<'), command.find('>')) {
+        (Some(start), Some(end)) if start < end => command[start + 1..end].to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Read a line of at most `MAX_LINE_LEN` bytes, `false` if the connection is closed.
+/// Fails with `InvalidData` if the line is longer, or `TimedOut` if it's not received within `COMMAND_TIMEOUT`.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R, line: &mut Vec<u8>) -> io::Result<bool> {
+    line.clear();
+    let read = timeout(COMMAND_TIMEOUT, (&mut *reader).take(MAX_LINE_LEN as u64).read_until(b'\n', line))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Timeout"))??;
+    if read >= MAX_LINE_LEN && !line.ends_with(b"\n") {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Line too long"));
+    }
+    Ok(read > 0)
+}
+
+/// Read the mail data until the line of a single dot, `None` if the connection is closed before the end.
+/// The data over `MAX_MESSAGE_SIZE` is read but discarded.
+async fn read_data<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        if !read_line(reader, &mut line).await? {
+            return Ok(None);
+        }
+        if line == b".\r\n" || line == b".\n" {
+            return Ok(Some(data));
+        }
+        if data.len() <= MAX_MESSAGE_SIZE {
+            let line = if line.starts_with(b".") { &line[1..] } else { &line[..] };
+            data.extend_from_slice(line);
+        }
+    }
+}
+
+/// Receives the delivery status notifications of the notifications through SMTP or LMTP,
+/// and moves the returned notifications to `Bounced`.
+#[derive(Clone)]
+pub struct BounceReceiver {
+    model: Model,
+    /// Only the recipients of this domain are accepted.
+    bounce_domain: String,
+    /// The VERP addresses are ignored without it, the bounces are matched by the `Message-ID` only.
+    verp: Option<VerpAddresses>,
+    events: broadcast::Sender<NotifyEvent>,
+    webhooks: WebhookSender,
+}
+
+impl BounceReceiver {
+    pub fn new(model: Model, notify_service: &EmailNotifyService, bounce_domain: &str) -> Self {
+        Self {
+            model,
+            bounce_domain: bounce_domain.to_string(),
+            verp: notify_service.verp(),
+            events: notify_service.events(),
+            webhooks: WebhookSender::new(),
+        }
+    }
+
+    /// Accept the inbound mails on the address in background, return the address listening on.
+    /// Must be called within the tokio runtime.
+    pub async fn listen(self, addr: &str) -> io::Result<SocketAddr> {
+        let mut listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        log::info!("Bounce receiver listening on {}", &local_addr);
+        let sessions = Arc::new(Semaphore::new(MAX_SESSIONS));
+
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((mut stream, peer)) => {
+                        let permit = match sessions.clone().try_acquire_owned() {
+                            Ok(permit) => permit,
+                            Err(_) => {
+                                log::warn!("Refuse bounce session from {} over {} sessions", peer, MAX_SESSIONS);
+                                stream.write_all(b"421 4.3.2 Too many sessions, try again later\r\n").await.ok();
+                                continue;
+                            }
+                        };
+                        let receiver = self.clone();
+                        tokio::spawn(async move {
+                            if let Err(err) = receiver.handle_session(stream).await {
+                                log::warn!("Bounce session from {} failed: {}", peer, err);
+                            }
+                            drop(permit);
+                        });
+                    }
+                    Err(err) => log::error!("Failed to accept bounce session: {}", err),
+                }
+            }
+        });
+
+        Ok(local_addr)
+    }
+
+    fn is_bounce_address(&self, address: &str) -> bool {
+        address.rfind('@')
+            .map_or(false, |idx| address[idx + 1..].eq_ignore_ascii_case(&self.bounce_domain))
+    }
+
+    /// The session is closed with a reply on a too long line or a timeout.
+    async fn handle_session(&self, mut stream: TcpStream) -> io::Result<()> {
+        let (reader, mut writer) = stream.split();
+        let mut reader = BufReader::new(reader);
+        let result = self.serve(&mut reader, &mut writer).await;
+        if let Err(err) = &result {
+            let reply: Option<&[u8]> = match err.kind() {
+                io::ErrorKind::InvalidData => Some(b"500 5.5.6 Line too long\r\n"),
+                io::ErrorKind::TimedOut => Some(b"421 4.4.2 Timeout, closing connection\r\n"),
+                _ => None,
+            };
+            if let Some(reply) = reply {
+                writer.write_all(reply).await.ok();
+            }
+        }
+        result
+    }
+
+    async fn serve<R, W>(&self, reader: &mut R, writer: &mut W) -> io::Result<()>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        writer.write_all(format!("220 {} ESMTP\r\n", &self.bounce_domain).as_bytes()).await?;
+
+        // LMTP replies the data once for each accepted recipient.
+        let mut lmtp = false;
+        let mut recipients = 0;
+        // The first VERP recipient if any.
+        let mut verp_recipient: Option<String> = None;
+        let mut line = Vec::new();
+        loop {
+            if !read_line(reader, &mut line).await? {
+                return Ok(());
+            }
+            let command = String::from_utf8_lossy(&line).trim_end().to_string();
+            let verb = command.split(' ').next().unwrap_or_default().to_uppercase();
+            let reply = match verb.as_str() {
+                "EHLO" | "HELO" | "LHLO" => {
+                    lmtp = verb == "LHLO";
+                    recipients = 0;
+                    verp_recipient = None;
+                    format!("250 {}", &self.bounce_domain)
+                }
+                "MAIL" | "RSET" => {
+                    recipients = 0;
+                    verp_recipient = None;
+                    "250 OK".to_string()
+                }
+                "RCPT" if self.is_bounce_address(&path(&command)) => {
+                    recipients += 1;
+                    verp_recipient = verp_recipient.or_else(|| Some(path(&command)).filter(|addr| is_verp_address(addr)));
+                    "250 OK".to_string()
+                }
+                "RCPT" => "550 5.1.1 Not a bounce address".to_string(),
+                "DATA" if recipients == 0 => "554 5.5.1 No valid recipients".to_string(),
+                "DATA" => {
+                    writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await?;
+                    let data = timeout(DATA_TIMEOUT, read_data(reader))
+                        .await
+                        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Timeout"))??;
+                    let data = match data {
+                        Some(data) => data,
+                        None => return Ok(()),
+                    };
+                    let reply = if data.len() > MAX_MESSAGE_SIZE {
+                        "552 5.3.4 Message too big"
+                    } else {
+                        match self.process(&String::from_utf8_lossy(&data), verp_recipient.as_deref()).await {
+                            Ok(_) => "250 OK",
+                            Err(err) => {
+                                log::error!("Failed to process bounce: {:?}", err);
+                                "451 4.3.0 Temporary failure"
+                            }
+                        }
+                    };
+                    let count = if lmtp { recipients } else { 1 };
+                    recipients = 0;
+                    verp_recipient = None;
+                    vec![reply; count].join("\r\n")
+                }
+                "NOOP" => "250 OK".to_string(),
+                "QUIT" => {
+                    writer.write_all(b"221 Bye\r\n").await?;
+                    return Ok(());
+                }
+                _ => "502 5.5.2 Command not implemented".to_string(),
+            };
+            writer.write_all(format!("{}\r\n", reply).as_bytes()).await?;
+        }
+    }
+
+    /// Apply the delivery status notification to the notification it returns,
+    /// the mails other than a report of the failed recipients of a notification are dropped.
+    ///
+    /// The notification is found by the `message_id` in `verp_recipient` if it's correctly signed,
+    /// or by the `Message-ID` of the returned mail otherwise.
+    async fn process(&self, data: &str, verp_recipient: Option<&str>) -> Result<(), model::Error> {
+        let report = match parse_report(data) {
+            Some(report) => report,
+            None => {
+                log::info!("Drop inbound mail without delivery status");
+                return Ok(());
+            }
+        };
+        let verp_id = verp_recipient.and_then(|addr| self.verp.as_ref().and_then(|verp| verp.message_id(addr)));
+        if let (Some(addr), None) = (verp_recipient, &verp_id) {
+            log::warn!("Ignore VERP address {} with invalid signature", addr);
+        }
+        let result = match (&verp_id, &report.message_id) {
+            (Some(id), _) => self.model.get_notification_by_message_id(id).await,
+            (None, Some(message_id)) => self.model.get_notification_by_mail_id(message_id).await,
+            (None, None) => {
+                log::warn!("Drop delivery status notification without the Message-ID of the returned mail");
+                return Ok(());
+            }
+        };
+        let mut notify = match result {
+            Ok(notify) => notify,
+            Err(model::Error::NoRecord) => {
+                log::warn!("Drop delivery status notification of unknown message");
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut bounced = Vec::new();
+        let mut bounced_indices = Vec::new();
+        for recipient in report.recipients.iter().filter(|r| r.is_failed()) {
+            let index = notify.recipients
+                .iter()
+                .position(|state| state.address.eq_ignore_ascii_case(&recipient.address));
+            match index {
+                Some(index) => {
+                    notify.recipients[index].status = NotifyState::Bounced(recipient.detail());
+                    bounced.push(recipient);
+                    bounced_indices.push(index);
+                }
+                None => log::warn!("Bounced {} is not a recipient of notification {}", &recipient.address, &notify._id),
+            }
+        }
+        let first = match bounced.first() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+
+        let status_changed = notify.status.is_sent() && !notify.recipients.iter().any(|state| state.status.is_sent());
+        if status_changed {
+            notify.status = NotifyState::Bounced(first.detail());
+        }
+        self.model.record_notification_bounce(&notify, &bounced_indices, status_changed).await?;
+        log::info!("Notification {} bounced by {} recipients", &notify._id, bounced.len());
+
+        for recipient in bounced.iter().filter(|r| is_address_failure(&r.status)) {
+            log::info!("Suppress {} for the hard bounce: {}", &recipient.address, recipient.detail());
+            self.model
+                .add_suppression(&notify.sender_profile, &recipient.address, SuppressionReason::HardBounce, Some(recipient.detail()))
+                .await?;
+        }
+
+        if status_changed {
+            let profile = sender_profile(&self.model, &notify).await.ok();
+            publish_event(&self.events, &self.webhooks, &notify, profile.as_ref());
+        }
+        Ok(())
+    }
+}
@@ -1,16 +1,123 @@
-use model::{NotifyProfile, NotifyState, Service};
-use smtp::{AuthCommand, Error as SMTPError, MIMEBody, MailBuilder, SMTPClient, SMTPClientTCP, SMTPClientTLS, mail::MailData};
-use std::{fmt, sync::mpsc::{channel, Receiver, SendError, Sender}, thread::spawn, time::Duration};
+use bytes::Bytes;
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+use model::{AttachmentDisposition, NotifyProfile, NotifyState, RecipientState, Service, SuppressionReason};
+use smtp::{Error as SMTPError, MIMEBody, MailBuilder, Reply, mail::MailData, mime::ContentDisposition};
+use std::{cell::RefCell, fmt, sync::mpsc::{channel, Receiver, RecvTimeoutError, SendError, Sender}, thread::spawn, time::{Duration, Instant}};
+use tokio::sync::broadcast;
 
 use crate::model::{self, EmailNotify, Model};
 
+use super::{bounce::{VerpAddresses, is_address_failure, is_enhanced_code}, rate_limit::RateLimiter, smtp_pool::SMTPPool, smtp_test::{self, ConnectionReport}, tracking::TrackingLinks, unsubscribe::UnsubscribeLinks, webhook::{NotifyEvent, WebhookSender}};
+
+/// Pooled SMTP sessions without any mail sent in this duration will be closed.
+const SMTP_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// Interval of sending `NOOP` to keep the pooled SMTP sessions alive.
+const SMTP_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+/// Status events buffered for each subscriber, the slow ones miss the older events.
+const NOTIFY_EVENT_CAPACITY: usize = 1024;
+
 #[derive(Debug)]
-enum Error {
+pub(crate) enum Error {
     ModelError(model::Error),
     MissingServiceProfile,
+    InvalidAttachment,
     ConnectFailed(SMTPError),
     AuthError(SMTPError),
     SendError(SMTPError),
+    RecipientsRejected,
+}
+
+impl Error {
+    /// The SMTP reply caused the error, if any.
+    fn reply(&self) -> Option<&Reply> {
+        fn error_reply(err: &SMTPError) -> Option<&Reply> {
+            match err {
+                SMTPError::ErrorReply(reply) => Some(reply),
+                SMTPError::HandshakeError(err) => error_reply(err),
+                _ => None,
+            }
+        }
+        match self {
+            Error::ConnectFailed(err) | Error::AuthError(err) | Error::SendError(err) => error_reply(err),
+            _ => None,
+        }
+    }
+
+    /// The notification might be delivered through another relay.
+    fn is_relay_failure(&self) -> bool {
+        match self {
+            Error::ConnectFailed(_) => true,
+            Error::AuthError(err) | Error::SendError(err) => is_transient(err),
+            _ => false,
+        }
+    }
+}
+
+/// A permanent rejection of the recipient address itself, rather than of the mail or by a policy.
+fn is_hard_bounce(reply: &Reply) -> bool {
+    let enhanced_code = reply.text_lines
+        .first()
+        .and_then(|line| line.split_whitespace().next())
+        .filter(|code| is_enhanced_code(code));
+    match enhanced_code {
+        Some(code) => is_address_failure(code),
+        None => reply.code == 550 || reply.code == 551 || reply.code == 553,
+    }
+}
+
+/// Publish the new state of the notification to the subscribers,
+/// and post it to the webhook of the request or its sender profile.
+/// Must be called within the tokio runtime.
+pub(super) fn publish_event(events: &broadcast::Sender<NotifyEvent>, webhooks: &WebhookSender, notify: &EmailNotify, profile: Option<&NotifyProfile>) {
+    let (status, error) = match &notify.status {
+        NotifyState::Pending => ("Pending", None),
+        NotifyState::Sent => ("Sent", None),
+        NotifyState::Error(err, _) => ("Error", Some(err.clone())),
+        NotifyState::Suppressed => ("Suppressed", None),
+        NotifyState::Bounced(detail) => ("Bounced", Some(detail.clone())),
+    };
+    let event = NotifyEvent {
+        sender_profile: notify.sender_profile.clone(),
+        message_id: hex::encode(notify._id.bytes()),
+        status,
+        error,
+        attempts: notify.attempts,
+    };
+    // Fails only if there is no subscriber.
+    events.send(event.clone()).ok();
+
+    let secret = profile.and_then(|profile| profile.webhook_secret.clone());
+    if let Some(url) = notify.webhook_url.clone() {
+        webhooks.post_public(url, secret, event);
+    } else if let Some(url) = profile.and_then(|profile| profile.webhook_url.clone()) {
+        webhooks.post(url, secret, event);
+    }
+}
+
+pub(super) async fn sender_profile(model: &Model, notify: &EmailNotify) -> Result<NotifyProfile, Error> {
+    let service_profile = model
+        .get_service_by_id(&notify.sender_profile)
+        .await
+        .map_err(|err| match err {
+            model::Error::NoRecord => Error::MissingServiceProfile,
+            err => err.into(),
+        })?;
+
+    match service_profile {
+        Service::EmailNotify(profile) => Ok(profile),
+        _ => Err(Error::MissingServiceProfile),
+    }
+}
+
+/// Lost connection or a transient negative reply.
+fn is_transient(err: &SMTPError) -> bool {
+    match err {
+        SMTPError::IOError(_) | SMTPError::ParseError(_) => true,
+        SMTPError::ErrorReply(reply) => reply.is_transient_negative(),
+        SMTPError::HandshakeError(err) => is_transient(err),
+        _ => false,
+    }
 }
 
 impl From<model::Error> for Error {
@@ -19,18 +126,13 @@ impl From<model::Error> for Error {
     }
 }
 
-// impl From<SMTPError> for Error {
-//     fn from(err: SMTPError) -> Self {
-//         Error::SMTPError(err)
-//     }
-// }
-
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::AuthError(_) => fmt::write(f, format_args!("SMTP Authorization failed")),
             Error::ConnectFailed(_) => fmt::write(f, format_args!("Cannot connect to SMTP Server")),
             Error::MissingServiceProfile => fmt::write(f, format_args!("Missing service profile")),
+            Error::InvalidAttachment => fmt::write(f, format_args!("Invalid attachment")),
             Error::ModelError(_) => fmt::write(f, format_args!("Internal db error")),
             Error::SendError(SMTPError::ErrorReply(err)) => fmt::write(
                 f,
@@ -41,6 +143,7 @@ impl fmt::Display for Error {
                 ),
             ),
             Error::SendError(_) => fmt::write(f, format_args!("Internal SMTP error")),
+            Error::RecipientsRejected => fmt::write(f, format_args!("All recipients are rejected")),
         }
     }
 }
@@ -48,21 +151,41 @@ impl fmt::Display for Error {
 #[derive(Clone)]
 pub struct EmailNotifyService {
     mail_sender: Sender<()>,
+    events: broadcast::Sender<NotifyEvent>,
+    timeout: Duration,
+    unsubscribe: Option<UnsubscribeLinks>,
+    tracking: Option<TrackingLinks>,
+    verp: Option<VerpAddresses>,
 }
 
 impl EmailNotifyService {
-    pub fn new(model: Model, timeout: Duration) -> Self {
+    /// The notifications have the one-click unsubscribe links only if `unsubscribe` is given,
+    /// and the opens and clicks are tracked only if `tracking` is given and enabled by the sender profile.
+    /// The VERP envelope senders are used only if `verp` is given and `verp_domain` is set by the sender profile.
+    pub fn new(model: Model, timeout: Duration, unsubscribe: Option<UnsubscribeLinks>, tracking: Option<TrackingLinks>, verp: Option<VerpAddresses>) -> Self {
         let (sender, receiver) = channel::<()>();
+        let (events, _) = broadcast::channel(NOTIFY_EVENT_CAPACITY);
 
         let service = PushService {
-            timeout,
             model,
             notify_receiver: receiver,
+            pool: RefCell::new(SMTPPool::new(timeout, SMTP_IDLE_TIMEOUT)),
+            limiter: RefCell::new(RateLimiter::new()),
+            webhooks: WebhookSender::new(),
+            events: events.clone(),
+            unsubscribe: unsubscribe.clone(),
+            tracking: tracking.clone(),
+            verp: verp.clone(),
         };
         spawn(move || service.start());
 
         Self {
             mail_sender: sender,
+            events,
+            timeout,
+            unsubscribe,
+            tracking,
+            verp,
         }
     }
 
@@ -70,14 +193,77 @@ impl EmailNotifyService {
         self.mail_sender.send(())
     }
 
-    
+    /// Receive the status events of all the notifications sent after subscribing.
+    pub fn subscribe(&self) -> broadcast::Receiver<NotifyEvent> {
+        self.events.subscribe()
+    }
+
+    /// Publish the status events changed outside of sending, e.g. by the bounces.
+    pub(super) fn events(&self) -> broadcast::Sender<NotifyEvent> {
+        self.events.clone()
+    }
+
+    /// Verifies the VERP addresses of the bounces.
+    pub(super) fn verp(&self) -> Option<VerpAddresses> {
+        self.verp.clone()
+    }
+
+    /// Connect to the SMTP server of the profile with the same timeout as sending notifications,
+    /// this blocks until all the stages are done.
+    pub fn test_connection(&self, profile: &NotifyProfile, recipient: Option<&str>) -> ConnectionReport {
+        smtp_test::test_connection(profile, self.timeout, recipient)
+    }
+
+    /// Build the message of a notification exactly as it would be sent, without sending it.
+    pub fn preview(&self, notify: &EmailNotify, profile: &NotifyProfile) -> Result<String, String> {
+        let mail = PushService::build_mail(notify, profile, &[], self.unsubscribe.as_ref(), self.tracking.as_ref())
+            .map_err(|err| err.to_string())?;
+        let data: Bytes = mail.into();
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    }
+
+    /// The sender profile and the address to unsubscribe by the token of an unsubscribe link.
+    pub fn verify_unsubscribe(&self, token: &str) -> Option<(ObjectId, String)> {
+        self.unsubscribe.as_ref().and_then(|links| links.verify(token))
+    }
+
+    /// The notification opened by the token of a tracking pixel.
+    pub fn verify_open(&self, token: &str) -> Option<ObjectId> {
+        self.tracking.as_ref().and_then(|links| links.verify_open(token))
+    }
+
+    /// The notification and the link clicked by the token of a click link.
+    pub fn verify_click(&self, token: &str) -> Option<(ObjectId, String)> {
+        self.tracking.as_ref().and_then(|links| links.verify_click(token))
+    }
 }
 
 
+/// A notification accepted by a relay.
+struct Delivery {
+    recipients: Vec<RecipientState>,
+    relay: String,
+    /// Reply of the mail data, or of the last recipient if all of them are rejected.
+    reply: Reply,
+    /// The recipients permanently rejected and the replies.
+    bounced: Vec<(String, String)>,
+}
+
+fn set_reply(notify: &mut EmailNotify, reply: &Reply) {
+    notify.reply_code = Some(reply.code as i32);
+    notify.reply_text = Some(reply.text_lines.join("\n"));
+}
+
 struct PushService {
-    timeout: Duration,
     model: Model,
     notify_receiver: Receiver<()>,
+    pool: RefCell<SMTPPool>,
+    limiter: RefCell<RateLimiter>,
+    webhooks: WebhookSender,
+    events: broadcast::Sender<NotifyEvent>,
+    unsubscribe: Option<UnsubscribeLinks>,
+    tracking: Option<TrackingLinks>,
+    verp: Option<VerpAddresses>,
 }
 
 impl PushService {
@@ -92,43 +278,144 @@ impl PushService {
         rt.block_on(async move {
             log::info!("Email notify serice up");
             log::debug!("Start processing existed notifications");
-            if let Err(err) = self.dequeue_notify().await {
+            let mut next_attempt = self.dequeue_notify().await.unwrap_or_else(|err| {
                 log::error!("{:?}", err);
-            }
+                None
+            });
 
             loop {
                 log::debug!("Waiting for notify");
-                if let Err(_) = self.notify_receiver.recv() {
-                    log::info!("Shutting down notify service");
-                    break;
+                let timeout = match next_attempt {
+                    Some(time) => time.saturating_duration_since(Instant::now()).min(SMTP_KEEP_ALIVE_INTERVAL),
+                    None => SMTP_KEEP_ALIVE_INTERVAL,
+                };
+                match self.notify_receiver.recv_timeout(timeout) {
+                    Ok(_) => (),
+                    Err(RecvTimeoutError::Timeout) if next_attempt.map_or(false, |time| time <= Instant::now()) => {
+                        log::debug!("Continue sending the pending notifications");
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.pool.borrow_mut().keep_alive();
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        log::info!("Shutting down notify service");
+                        self.pool.borrow_mut().close_all();
+                        break;
+                    }
                 }
 
                 log::debug!("Start sending notification");
-                if let Err(err) = self.dequeue_notify().await {
+                next_attempt = self.dequeue_notify().await.unwrap_or_else(|err| {
                     log::error!("{:?}", err);
-                }
+                    None
+                });
             }
         });
     }
     
-    async fn dequeue_notify(&self) -> Result<(), Error> {
+    /// Send all the pending notifications in order of priority,
+    /// except those held by the rate limit of their sender profile.
+    ///
+    /// Return the earliest time to retry the held notifications,
+    /// or now if new notifications are queued before all the pending ones are sent.
+    async fn dequeue_notify(&self) -> Result<Option<Instant>, Error> {
+        // The notifications queued so far are all included in this round.
+        while self.notify_receiver.try_recv().is_ok() {}
         let notifications = self.model.get_pending_notifications().await.map_err(Error::from)?;
         
         let iter = notifications
             .into_iter()
             .filter(|n| n.status == NotifyState::Pending);
 
+        let mut next_attempt: Option<Instant> = None;
+        let mut hold_until = |delay: Duration| {
+            let time = Instant::now() + delay;
+            next_attempt = Some(next_attempt.map_or(time, |next| next.min(time)));
+        };
+
         for mut notify in iter {
+            if let Some(time) = &notify.next_attempt_at {
+                if let Ok(delay) = (time.0 - Utc::now()).to_std() {
+                    hold_until(delay);
+                    continue;
+                }
+            }
+
+            let profile = self.sender_profile(&notify).await;
+            let suppressed = self.suppressed_recipients(&notify).await?;
+            if !suppressed.is_empty() && suppressed.len() == notify.mail.recipients().len() {
+                log::info!("All recipients of notification {} are suppressed", &notify.message_id);
+                notify.set_suppressed();
+                self.model
+                    .update_notification(&notify)
+                    .await
+                    .map_err(Error::from)?;
+                self.publish_event(&notify, profile.as_ref().ok());
+                continue;
+            }
+
+            let delay = match &profile {
+                Ok(NotifyProfile { rate_limit: Some(rate_limit), .. }) => {
+                    self.limiter.borrow_mut().try_acquire(&notify.sender_profile, rate_limit)
+                }
+                _ => None,
+            };
+            if let Some(delay) = delay {
+                log::debug!("Notification held by the rate limit for {:?}", delay);
+                let time = Utc::now() + chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::zero());
+                notify.next_attempt_at = Some(time.into());
+                self.model
+                    .update_notification(&notify)
+                    .await
+                    .map_err(Error::from)?;
+                hold_until(delay);
+                continue;
+            }
+            notify.next_attempt_at = None;
+            notify.attempts += 1;
+            if notify.first_attempt_at.is_none() {
+                notify.first_attempt_at = Some(Utc::now().into());
+            }
 
-            let result = self.try_send_notify(&notify).await;
+            let (profile, result) = match profile {
+                Ok(profile) => {
+                    notify.tracked = profile.tracking && self.tracking.is_some() && notify.mail.html.is_some();
+                    let result = self.try_send_notify(&notify, &profile, &suppressed);
+                    (Some(profile), result)
+                }
+                Err(err) => (None, Err(err)),
+            };
+            let mut bounced = Vec::new();
+            let result = result
+                .and_then(|delivery| {
+                    let accepted = delivery.recipients.iter().any(|r| r.status.is_sent());
+                    notify.recipients = delivery.recipients;
+                    notify.recipients.extend(suppressed.into_iter().map(|address| RecipientState {
+                        address,
+                        status: NotifyState::Suppressed,
+                    }));
+                    notify.relay = Some(delivery.relay);
+                    bounced = delivery.bounced;
+                    set_reply(&mut notify, &delivery.reply);
+                    if accepted {
+                        Ok(())
+                    } else {
+                        Err(Error::RecipientsRejected)
+                    }
+                });
 
             notify.status = match result {
                 Ok(_) => {
                     log::debug!("Notification email sent");
+                    notify.sent_at = Some(Utc::now().into());
                     NotifyState::Sent
                 },
                 Err(err) => {
                     log::warn!("Failed to send an email notify {:?}", err);
+                    if let Some(reply) = err.reply() {
+                        set_reply(&mut notify, reply);
+                    }
                     NotifyState::Error(format!("{}", err), format!("{:?}", err))
                 }
             };
@@ -139,77 +426,190 @@ impl PushService {
                 .map_err(Error::from)?;
             
             log::debug!("Notification updated");
-        }
 
-        Ok(())
-    }
-
-    async fn try_send_notify(&self, notify: &EmailNotify) -> Result<(), Error> {
-        log::debug!("Try sending notification to {}", &notify.mail.to);
-        let service_profile = self.model
-            .get_service_by_id(&notify.sender_profile)
-            .await
-            .map_err(|err| match err {
-                model::Error::NoRecord => Error::MissingServiceProfile,
-                err => err.into(),
-            })?;
-
-        let result = match service_profile {
-            Service::EmailNotify(service_profile) if service_profile.tls => {
-                let client = self.connect_tls(&service_profile)?;
-                self.send_notify(&notify, &service_profile, client)
+            for (address, reply) in bounced {
+                log::info!("Suppress {} for the hard bounce: {}", &address, &reply);
+                self.model
+                    .add_suppression(&notify.sender_profile, &address, SuppressionReason::HardBounce, Some(reply))
+                    .await
+                    .map_err(Error::from)?;
             }
-            Service::EmailNotify(service_profile) => {
-                let client = self.connect_smtp(&service_profile)?;
-                self.send_notify(&notify, &service_profile, client)
+
+            self.publish_event(&notify, profile.as_ref());
+
+            if self.notify_receiver.try_recv().is_ok() {
+                log::debug!("New notifications queued, restart from the highest priority");
+                return Ok(Some(Instant::now()));
             }
-            _ => Err(Error::MissingServiceProfile),
-        };
+        }
 
-        result
+        Ok(next_attempt)
     }
 
-    
+    fn publish_event(&self, notify: &EmailNotify, profile: Option<&NotifyProfile>) {
+        publish_event(&self.events, &self.webhooks, notify, profile);
+    }
 
-    fn build_mail(notify: &EmailNotify, profile: &NotifyProfile) -> MailData {
-        let content = MIMEBody::new(&notify.mail.content_type).text(&notify.mail.body);
+    async fn sender_profile(&self, notify: &EmailNotify) -> Result<NotifyProfile, Error> {
+        sender_profile(&self.model, notify).await
+    }
 
-        let mail = MailBuilder::new()
-            .from((&profile.name, &profile.email_address))
-            .to(notify.mail.to.as_str())
-            .message_id(&notify.message_id)
-            .subject(&notify.mail.subject)
-            .body(content)
-            .build();
+    /// Recipients of the notification on the suppression list of its sender profile.
+    async fn suppressed_recipients(&self, notify: &EmailNotify) -> Result<Vec<String>, Error> {
+        let recipients = notify.mail.recipients();
+        let suppressions = self.model
+            .find_suppressions(&[notify.sender_profile.clone()], &recipients)
+            .await
+            .map_err(Error::from)?;
 
-        mail
+        Ok(recipients
+            .into_iter()
+            .filter(|addr| suppressions.iter().any(|s| s.address == addr.to_lowercase()))
+            .map(|addr| addr.to_string())
+            .collect())
     }
 
-    fn send_notify<S: std::io::Read + std::io::Write>(&self, notify: &EmailNotify, profile: &NotifyProfile, mut client: SMTPClient<S>) -> Result<(), Error> {
-        let mail = Self::build_mail(&notify, &profile);
-        client.auth(AuthCommand::Plain(
-                None,
-                profile.username.clone(),
-                profile.password.clone(),
-            ))
-            .map_err(|err| Error::AuthError(err))?
-            .send(&profile.email_address, &notify.mail.to, mail)
-            .map_err(|err| Error::SendError(err))?
-            .quit()
-            .err();
-
-        Ok(())
+    /// Send the notification through the relays of its sender profile in order,
+    /// fail over to the next relay on connection errors and transient replies.
+    /// The `suppressed` recipients are left out.
+    ///
+    /// Return the state of each recipient and the relay which accepted the notification.
+    fn try_send_notify(&self, notify: &EmailNotify, profile: &NotifyProfile, suppressed: &[String]) -> Result<Delivery, Error> {
+        log::debug!("Try sending notification to {}", notify.mail.recipients().join(","));
+        let mail_from = match (&profile.verp_domain, &self.verp) {
+            (Some(domain), Some(verp)) => verp.address(&notify._id, domain).unwrap_or_else(|err| {
+                log::error!("Failed to sign VERP address: {:?}", err);
+                profile.email_address.clone()
+            }),
+            _ => profile.email_address.clone(),
+        };
+        let relays = profile.relays();
+        for (idx, relay) in relays.iter().enumerate() {
+            let has_fallback = idx + 1 < relays.len();
+            let mail = Self::build_mail(&notify, profile, suppressed, self.unsubscribe.as_ref(), self.tracking.as_ref())?;
+            let result = self.pool
+                .borrow_mut()
+                .send(&notify.sender_profile, relay, &mail_from, mail);
+
+            match result {
+                Ok((replies, _)) if has_fallback && replies.iter().all(|(_, reply)| reply.is_transient_negative()) => {
+                    log::warn!("All recipients deferred by relay {}, fail over to the next relay", &relay.smtp_address);
+                }
+                Ok((replies, data_reply)) => {
+                    // The mail data is not sent if all recipients are rejected.
+                    let reply = match data_reply {
+                        Some(reply) => reply,
+                        None => replies.last().map(|(_, reply)| reply.clone()).unwrap_or_default(),
+                    };
+                    let bounced = replies.iter()
+                        .filter(|(_, reply)| is_hard_bounce(reply))
+                        .map(|(address, reply)| (address.clone(), format!("{} {}", reply.code, reply.text_lines.join("\n"))))
+                        .collect();
+                    return Ok(Delivery {
+                        recipients: Self::recipient_states(replies),
+                        relay: relay.smtp_address.clone(),
+                        reply,
+                        bounced,
+                    });
+                }
+                Err(err) if has_fallback && err.is_relay_failure() => {
+                    log::warn!("Failed to send through relay {}: {:?}, fail over to the next relay", &relay.smtp_address, err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        unreachable!("The primary relay is always tried")
     }
 
-    fn connect_smtp(&self, profile: &NotifyProfile) -> Result<SMTPClientTCP, Error> {
-        let client = SMTPClient::connect_timeout(&profile.smtp_address, self.timeout)
-            .map_err(|err| Error::ConnectFailed(err))?;
-        Ok(client)
+    fn recipient_states(replies: Vec<(String, Reply)>) -> Vec<RecipientState> {
+        replies
+            .into_iter()
+            .map(|(address, reply)| RecipientState {
+                address,
+                status: if reply.is_positive_completion() {
+                    NotifyState::Sent
+                } else {
+                    NotifyState::Error(
+                        format!("Recipient rejected: {}: {}", reply.code, reply.text_lines.join("\r\n")),
+                        format!("{:?}", reply),
+                    )
+                },
+            })
+            .collect()
     }
-    
-    fn connect_tls(&self, profile: &NotifyProfile) -> Result<SMTPClientTLS, Error> {
-        let client = SMTPClient::connect_tls_timeout(&profile.smtp_address, self.timeout)
-            .map_err(|err| Error::ConnectFailed(err))?;
-        Ok(client)
+
+    /// The `suppressed` recipients are left out of the message, and the message to a single recipient
+    /// has the one-click unsubscribe headers of RFC 8058 if `unsubscribe` is given.
+    /// The links of the HTML body are tracked if `tracking` is given and enabled by the profile.
+    fn build_mail(notify: &EmailNotify, profile: &NotifyProfile, suppressed: &[String], unsubscribe: Option<&UnsubscribeLinks>, tracking: Option<&TrackingLinks>) -> Result<MailData, Error> {
+        let is_suppressed = |addr: &str| suppressed.iter().any(|s| s.eq_ignore_ascii_case(addr));
+        let mut builder = MailBuilder::new()
+            .from((&profile.name, &profile.email_address))
+            .message_id(&notify.message_id)
+            .subject(&notify.mail.subject);
+        match (&notify.mail.text, &notify.mail.html) {
+            (None, None) => {
+                builder = builder.body(MIMEBody::new(&notify.mail.content_type).text(&notify.mail.body));
+            }
+            (text, html) => {
+                if let Some(text) = text {
+                    builder = builder.alternative(MIMEBody::new("text/plain; charset=utf-8").text(text));
+                }
+                if let Some(html) = html {
+                    let html = match tracking.filter(|_| profile.tracking) {
+                        Some(tracking) => tracking.track_html(&notify._id, html).unwrap_or_else(|err| {
+                            log::error!("Failed to sign the tracking links: {:?}", err);
+                            html.clone()
+                        }),
+                        None => html.clone(),
+                    };
+                    builder = builder.alternative(MIMEBody::new("text/html; charset=utf-8").text(html));
+                }
+            }
+        }
+        if let Some(language) = &notify.mail.content_language {
+            builder = builder.content_language(language);
+        }
+        for addr in notify.mail.to.iter().filter(|addr| !is_suppressed(addr)) {
+            builder = builder.to(addr.as_str());
+        }
+        for addr in notify.mail.cc.iter().filter(|addr| !is_suppressed(addr)) {
+            builder = builder.cc(addr.as_str());
+        }
+        for addr in notify.mail.bcc.iter().filter(|addr| !is_suppressed(addr)) {
+            builder = builder.bc(addr.as_str());
+        }
+        // A link is of a single address, it can't be shared by the recipients of a message.
+        let recipients: Vec<&str> = notify.mail.recipients()
+            .into_iter()
+            .filter(|addr| !is_suppressed(addr))
+            .collect();
+        if let (Some(unsubscribe), [recipient]) = (unsubscribe, recipients.as_slice()) {
+            match unsubscribe.link(&notify.sender_profile, recipient) {
+                Ok(link) => {
+                    builder = builder
+                        .header("List-Unsubscribe", format!("<{}>", link))
+                        .header("List-Unsubscribe-Post", "List-Unsubscribe=One-Click");
+                }
+                Err(err) => log::error!("Failed to sign the unsubscribe link: {:?}", err),
+            }
+        }
+        for attachment in &notify.mail.attachments {
+            let content = openssl::base64::decode_block(&attachment.content)
+                .map_err(|_| Error::InvalidAttachment)?;
+            let filename = Some(attachment.filename.clone());
+            let mut part = MIMEBody::new(&attachment.content_type)
+                .base64(content)
+                .disposition(match attachment.disposition {
+                    AttachmentDisposition::Attachment => ContentDisposition::Attachment(filename),
+                    AttachmentDisposition::Inline => ContentDisposition::Inline(filename),
+                });
+            if let Some(content_id) = &attachment.content_id {
+                part = part.content_id(content_id);
+            }
+            builder = builder.body(part);
+        }
+
+        Ok(builder.build())
     }
 }
\ No newline at end of file
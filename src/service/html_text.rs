@@ -0,0 +1,165 @@
+/// Generate a plain text version of an HTML body, used as the text alternative
+/// when only HTML is provided.
+///
+/// Tags are stripped with line breaks kept for block elements, the content of
+/// `<head>`, `<script>` and `<style>` is dropped and common entities are decoded.
+pub fn html_to_text(html: &str) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+    let mut skip_until: Option<&str> = None;
+    let mut link: Option<String> = None;
+    let mut link_text_start = 0;
+
+    while let Some(start) = rest.find('<') {
+        if skip_until.is_none() {
+            push_text(&mut output, &rest[..start]);
+        }
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => {
+                rest = "";
+                break;
+            }
+        };
+        let tag = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        if let Some(skipped) = skip_until {
+            if closing && name == skipped {
+                skip_until = None;
+            }
+            continue;
+        }
+
+        match (name.as_str(), closing) {
+            ("head", false) => skip_until = Some("head"),
+            ("script", false) => skip_until = Some("script"),
+            ("style", false) => skip_until = Some("style"),
+            ("br", _) => output.push('\n'),
+            ("li", false) => {
+                push_line_break(&mut output);
+                output.push_str("- ");
+            }
+            ("a", false) => {
+                link = attribute(tag, "href");
+                link_text_start = output.len();
+            }
+            ("a", true) => {
+                if let Some(href) = link.take() {
+                    let label = output[link_text_start..].trim();
+                    if !href.starts_with('#') && label != href && !href.starts_with("mailto:") {
+                        output.push_str(&format!(" ({})", href));
+                    }
+                }
+            }
+            ("p", _) | ("div", _) | ("h1", _) | ("h2", _) | ("h3", _) | ("h4", _) | ("h5", _) | ("h6", _)
+            | ("ul", _) | ("ol", _) | ("table", _) | ("tr", _) | ("blockquote", _) | ("pre", _) | ("hr", _) => {
+                push_paragraph_break(&mut output);
+            }
+            ("td", true) | ("th", true) => output.push('\t'),
+            _ => (),
+        }
+    }
+    if skip_until.is_none() {
+        push_text(&mut output, rest);
+    }
+
+    output
+        .lines()
+        .map(|line| line.trim())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .split("\n\n")
+        .map(|paragraph| paragraph.trim_matches('\n'))
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Append the text with whitespaces collapsed and entities decoded.
+fn push_text(output: &mut String, text: &str) {
+    let mut rest = text;
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        if c.is_whitespace() {
+            if !output.ends_with(|c: char| c.is_whitespace()) && !output.is_empty() {
+                output.push(' ');
+            }
+            rest = &rest[c.len_utf8()..];
+        } else if c == '&' {
+            match rest.find(';').filter(|&end| end <= 10).and_then(|end| decode_entity(&rest[1..end]).map(|c| (end, c))) {
+                Some((end, decoded)) => {
+                    output.push(decoded);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    output.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        } else {
+            output.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+            u32::from_str_radix(&entity[2..], 16).ok().and_then(std::char::from_u32)
+        }
+        _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(std::char::from_u32),
+        _ => None,
+    }
+}
+
+fn push_line_break(output: &mut String) {
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+}
+
+fn push_paragraph_break(output: &mut String) {
+    push_line_break(output);
+    if !output.is_empty() && !output.ends_with("\n\n") {
+        output.push('\n');
+    }
+}
+
+/// Value of a quoted attribute in a tag.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let pattern = format!("{}=", name);
+    let mut search = 0;
+    while let Some(idx) = lower[search..].find(&pattern) {
+        let idx = search + idx;
+        let preceded = idx == 0 || lower[..idx].ends_with(|c: char| c.is_whitespace());
+        let value = &tag[idx + pattern.len()..];
+        if preceded {
+            let quote = value.chars().next()?;
+            if quote == '"' || quote == '\'' {
+                let end = value[1..].find(quote)?;
+                return Some(value[1..end + 1].to_string());
+            }
+            let end = value.find(|c: char| c.is_whitespace()).unwrap_or(value.len());
+            return Some(value[..end].to_string());
+        }
+        search = idx + pattern.len();
+    }
+    None
+}
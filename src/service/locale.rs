@@ -0,0 +1,168 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeZone, Utc};
+use serde_json::{Number, Value};
+
+/// A language tag such as `zh-Hans-CN`, normalized to lowercase language, title case script and uppercase region.
+#[derive(Clone)]
+pub struct Locale {
+    tag: String,
+    format: &'static LocaleFormat,
+}
+
+struct LocaleFormat {
+    language: &'static str,
+    decimal_separator: &'static str,
+    group_separator: &'static str,
+    date: &'static str,
+    datetime: &'static str,
+}
+
+const LOCALE_FORMATS: &[LocaleFormat] = &[
+    LocaleFormat {
+        language: "en",
+        decimal_separator: ".",
+        group_separator: ",",
+        date: "%b %-d, %Y",
+        datetime: "%b %-d, %Y %H:%M UTC%:z",
+    },
+    LocaleFormat {
+        language: "zh",
+        decimal_separator: ".",
+        group_separator: ",",
+        date: "%Y年%-m月%-d日",
+        datetime: "%Y年%-m月%-d日 %H:%M UTC%:z",
+    },
+    LocaleFormat {
+        language: "ja",
+        decimal_separator: ".",
+        group_separator: ",",
+        date: "%Y年%-m月%-d日",
+        datetime: "%Y年%-m月%-d日 %H:%M UTC%:z",
+    },
+    LocaleFormat {
+        language: "de",
+        decimal_separator: ",",
+        group_separator: ".",
+        date: "%d.%m.%Y",
+        datetime: "%d.%m.%Y %H:%M UTC%:z",
+    },
+    LocaleFormat {
+        language: "fr",
+        decimal_separator: ",",
+        group_separator: "\u{202f}",
+        date: "%d/%m/%Y",
+        datetime: "%d/%m/%Y %H:%M UTC%:z",
+    },
+];
+
+const LOCALE_TAG_MAX_LEN: usize = 35;
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self {
+            tag: "en".to_string(),
+            format: &LOCALE_FORMATS[0],
+        }
+    }
+}
+
+impl Locale {
+    pub fn parse(tag: &str) -> Option<Self> {
+        if tag.is_empty() || tag.len() > LOCALE_TAG_MAX_LEN {
+            return None;
+        }
+        let mut subtags = Vec::new();
+        for (idx, subtag) in tag.split(|c| c == '-' || c == '_').enumerate() {
+            let valid = !subtag.is_empty()
+                && subtag.len() <= 8
+                && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+                && (idx > 0 || subtag.chars().all(|c| c.is_ascii_alphabetic()));
+            if !valid {
+                return None;
+            }
+            subtags.push(match (idx, subtag.len()) {
+                (0, _) => subtag.to_ascii_lowercase(),
+                (_, 2) => subtag.to_ascii_uppercase(),
+                (_, 4) => subtag[..1].to_ascii_uppercase() + &subtag[1..].to_ascii_lowercase(),
+                _ => subtag.to_string(),
+            });
+        }
+
+        let format = LOCALE_FORMATS
+            .iter()
+            .find(|f| f.language == subtags[0])
+            .unwrap_or(&LOCALE_FORMATS[0]);
+
+        Some(Self {
+            tag: subtags.join("-"),
+            format,
+        })
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// The tag followed by its less specific forms, e.g. `zh-Hans-CN`, `zh-Hans`, `zh`.
+    pub fn fallbacks(&self) -> Vec<&str> {
+        let mut tags = vec![self.tag.as_str()];
+        let mut tag = self.tag.as_str();
+        while let Some(idx) = tag.rfind('-') {
+            tag = &tag[..idx];
+            tags.push(tag);
+        }
+        tags
+    }
+
+    pub fn format_number(&self, number: &Number) -> String {
+        // The floats are written without exponent, e.g. `1e21` as `1000000000000000000000`.
+        let text = match number.as_f64() {
+            Some(float) if number.is_f64() => float.to_string(),
+            _ => number.to_string(),
+        };
+        let (sign, text) = match text.strip_prefix('-') {
+            Some(text) => ("-", text),
+            None => ("", text),
+        };
+        let (integer, fraction) = match text.find('.') {
+            Some(idx) => (&text[..idx], Some(&text[idx + 1..])),
+            None => (text, None),
+        };
+
+        let mut output = sign.to_string();
+        for (idx, digit) in integer.chars().enumerate() {
+            if idx > 0 && (integer.len() - idx) % 3 == 0 {
+                output.push_str(self.format.group_separator);
+            }
+            output.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            output.push_str(self.format.decimal_separator);
+            output.push_str(fraction);
+        }
+        output
+    }
+
+    /// Format a date from an RFC 3339 date time, a `YYYY-MM-DD` date or a unix timestamp in seconds.
+    pub fn format_date(&self, value: &Value) -> Option<String> {
+        if let Some(date) = value.as_str().and_then(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()) {
+            return Some(date.format(self.format.date).to_string());
+        }
+        parse_datetime(value).map(|datetime| datetime.format(self.format.date).to_string())
+    }
+
+    /// Format a date time from an RFC 3339 date time or a unix timestamp in seconds, the offset is preserved.
+    pub fn format_datetime(&self, value: &Value) -> Option<String> {
+        parse_datetime(value).map(|datetime| datetime.format(self.format.datetime).to_string())
+    }
+}
+
+fn parse_datetime(value: &Value) -> Option<DateTime<FixedOffset>> {
+    match value {
+        Value::String(text) => DateTime::parse_from_rfc3339(text).ok(),
+        Value::Number(num) => num
+            .as_i64()
+            .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+            .map(|datetime| datetime.with_timezone(&FixedOffset::east(0))),
+        _ => None,
+    }
+}
@@ -1,3 +1,26 @@
+mod bounce;
 mod email_notify;
+mod html_text;
+mod locale;
+mod rate_limit;
+mod retention;
+mod signature;
+mod smtp_pool;
+mod smtp_test;
+mod template;
+mod tracking;
+mod unsubscribe;
+mod webhook;
 
-pub use email_notify::EmailNotifyService;
\ No newline at end of file
+pub use bounce::{BounceReceiver, VerpAddresses};
+pub use email_notify::EmailNotifyService;
+pub use html_text::html_to_text;
+pub use locale::Locale;
+pub use retention::start_purge_task;
+#[cfg(test)]
+pub(crate) use smtp_pool::SMTPPool;
+pub use smtp_test::ConnectionReport;
+pub use template::{Escape, Template, TemplateError};
+pub use tracking::TrackingLinks;
+pub use unsubscribe::UnsubscribeLinks;
+pub use webhook::{NotifyEvent, is_public_webhook_url};
\ No newline at end of file
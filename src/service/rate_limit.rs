@@ -0,0 +1,39 @@
+use std::{collections::{HashMap, VecDeque}, time::{Duration, Instant}};
+
+use mongodb::bson::oid::ObjectId;
+
+use crate::model::RateLimit;
+
+/// Send time of the recent notifications of each `EmailNotify` service profile,
+/// in a sliding window of the rate limit interval.
+pub(super) struct RateLimiter {
+    sent: HashMap<ObjectId, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            sent: HashMap::new(),
+        }
+    }
+
+    /// Record a notification of the service profile sent now if it's within the limit,
+    /// otherwise return the time to wait before it can be sent.
+    pub fn try_acquire(&mut self, service_id: &ObjectId, limit: &RateLimit) -> Option<Duration> {
+        let interval = Duration::from_secs(limit.interval.max(0) as u64);
+        let max_messages = limit.max_messages.max(1) as usize;
+        let now = Instant::now();
+
+        let window = self.sent.entry(service_id.clone()).or_default();
+        while window.front().map_or(false, |time| now.duration_since(*time) >= interval) {
+            window.pop_front();
+        }
+
+        if window.len() < max_messages {
+            window.push_back(now);
+            None
+        } else {
+            window.front().map(|time| interval - now.duration_since(*time))
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use crate::model::{self, Model, Retention, Service, days_ago};
+
+/// Interval of purging the notification history by the retention.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Purge the notification history periodically by the retention of each sender profile,
+/// the days not set by a profile are taken from the global `retention`.
+/// Must be called within the tokio runtime.
+pub fn start_purge_task(model: Model, retention: Retention) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = purge_by_retention(&model, retention).await {
+                log::error!("Failed to purge notification history: {:?}", err);
+            }
+        }
+    });
+}
+
+async fn purge_by_retention(model: &Model, global: Retention) -> Result<(), model::Error> {
+    let services = model.get_services_by_type("EmailNotify").await?;
+    for record in services {
+        let retention = match &record.service {
+            Service::EmailNotify(profile) => profile.retention.unwrap_or_default().or(global),
+            _ => continue,
+        };
+        if !retention.is_valid() {
+            log::warn!("Skip purging service {} with invalid retention {:?}", &record._id, retention);
+            continue;
+        }
+        let service_ids = [record._id];
+
+        if let Some(days) = retention.body_days {
+            let count = model.purge_notification_bodies(&service_ids, days_ago(days)).await?;
+            if count > 0 {
+                log::info!("Stripped {} notifications of service {} older than {} days", count, &service_ids[0], days);
+            }
+        }
+        if let Some(days) = retention.metadata_days {
+            let count = model.delete_notifications_before(&service_ids, days_ago(days)).await?;
+            if count > 0 {
+                log::info!("Deleted {} notifications of service {} older than {} days", count, &service_ids[0], days);
+            }
+        }
+    }
+    Ok(())
+}
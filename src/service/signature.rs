@@ -0,0 +1,9 @@
+use openssl::{error::ErrorStack, hash::MessageDigest, pkey::PKey, sign::Signer};
+
+/// HMAC-SHA256 of the payload keyed by the secret, shared by the signed links, VERP addresses and webhook events.
+pub(super) fn sign(secret: &str, payload: impl AsRef<[u8]>) -> Result<Vec<u8>, ErrorStack> {
+    let key = PKey::hmac(secret.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(payload.as_ref())?;
+    signer.sign_to_vec()
+}
@@ -0,0 +1,149 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use mongodb::bson::oid::ObjectId;
+use smtp::{AuthCommand, Endpoint, Error as SMTPError, MailReplies, Reply, SMTPClient, SMTPClientTCP, SMTPClientTLS, mail::MailData};
+
+use crate::model::Relay;
+
+use super::email_notify::Error;
+
+enum Connection {
+    TCP(SMTPClientTCP),
+    TLS(SMTPClientTLS),
+}
+
+impl Connection {
+    fn connect(relay: &Relay, timeout: Duration) -> Result<Self, Error> {
+        let endpoint = Endpoint::parse(&relay.smtp_address).map_err(|err| Error::ConnectFailed(err))?;
+        let connection = if relay.tls {
+            let client = SMTPClient::connect_tls_timeout(endpoint, timeout)
+                .map_err(|err| Error::ConnectFailed(err))?;
+            Connection::TLS(client)
+        } else {
+            let client = SMTPClient::connect_timeout(endpoint, timeout)
+                .map_err(|err| Error::ConnectFailed(err))?;
+            Connection::TCP(client)
+        };
+        Ok(connection)
+    }
+
+    fn auth(&mut self, relay: &Relay) -> Result<(), SMTPError> {
+        let auth = AuthCommand::Plain(None, relay.username.clone(), relay.password.clone());
+        match self {
+            Connection::TCP(client) => client.auth(auth).map(|_| ()),
+            Connection::TLS(client) => client.auth(auth).map(|_| ()),
+        }
+    }
+
+    fn send(&mut self, mail_from: &str, rcpt_to: &[String], mail: MailData) -> Result<MailReplies, SMTPError> {
+        match self {
+            Connection::TCP(client) => client.send_mail(mail_from, rcpt_to, mail),
+            Connection::TLS(client) => client.send_mail(mail_from, rcpt_to, mail),
+        }
+    }
+
+    fn noop(&mut self) -> Result<(), SMTPError> {
+        match self {
+            Connection::TCP(client) => client.noop().map(|_| ()),
+            Connection::TLS(client) => client.noop().map(|_| ()),
+        }
+    }
+
+    fn quit(&mut self) -> Result<(), SMTPError> {
+        match self {
+            Connection::TCP(client) => client.quit().map(|_| ()),
+            Connection::TLS(client) => client.quit().map(|_| ()),
+        }
+    }
+}
+
+struct Session {
+    relay: Relay,
+    connection: Connection,
+    last_active: Instant,
+}
+
+/// Authenticated SMTP sessions kept alive between notifications,
+/// one per relay of each `EmailNotify` service profile.
+pub(crate) struct SMTPPool {
+    timeout: Duration,
+    idle_timeout: Duration,
+    sessions: HashMap<(ObjectId, String), Session>,
+}
+
+impl SMTPPool {
+    pub fn new(timeout: Duration, idle_timeout: Duration) -> Self {
+        Self {
+            timeout,
+            idle_timeout,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Send a mail to all its recipients through the pooled session to the relay of the service profile,
+    /// return the `RCPT` reply of each recipient and the reply of the mail data if it's sent.
+    /// The session is dropped if anything goes wrong.
+    pub fn send(&mut self, service_id: &ObjectId, relay: &Relay, mail_from: &str, mail: MailData) -> Result<(Vec<(String, Reply)>, Option<Reply>), Error> {
+        let key = (service_id.clone(), relay.smtp_address.clone());
+        let mut session = self.acquire(&key, relay)?;
+        let recipients = mail.recipients().to_vec();
+
+        match session.connection.send(mail_from, &recipients, mail) {
+            Ok(replies) => {
+                session.last_active = Instant::now();
+                self.sessions.insert(key, session);
+                Ok((recipients.into_iter().zip(replies.recipients).collect(), replies.data))
+            }
+            Err(err) => {
+                session.connection.quit().ok();
+                Err(Error::SendError(err))
+            }
+        }
+    }
+
+    /// Send `NOOP` on every pooled session, evict the sessions which are idle for too long or not responding.
+    pub fn keep_alive(&mut self) {
+        let idle_timeout = self.idle_timeout;
+        self.sessions.retain(|_, session| {
+            if session.last_active.elapsed() >= idle_timeout {
+                log::debug!("Close idle SMTP session to {}", &session.relay.smtp_address);
+                session.connection.quit().ok();
+                false
+            } else if let Err(err) = session.connection.noop() {
+                log::debug!("Evict SMTP session to {}: {:?}", &session.relay.smtp_address, err);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    pub fn close_all(&mut self) {
+        for (_, mut session) in self.sessions.drain() {
+            session.connection.quit().ok();
+        }
+    }
+
+    fn acquire(&mut self, key: &(ObjectId, String), relay: &Relay) -> Result<Session, Error> {
+        if let Some(mut session) = self.sessions.remove(key) {
+            let reusable = &session.relay == relay
+                && session.last_active.elapsed() < self.idle_timeout
+                && session.connection.noop().is_ok();
+            if reusable {
+                log::debug!("Reuse SMTP session to {}", &relay.smtp_address);
+                return Ok(session);
+            }
+            session.connection.quit().ok();
+        }
+
+        log::debug!("Open SMTP session to {}", &relay.smtp_address);
+        let mut connection = Connection::connect(relay, self.timeout)?;
+        connection.auth(relay).map_err(|err| Error::AuthError(err))?;
+
+        Ok(Session {
+            relay: relay.clone(),
+            connection,
+            last_active: Instant::now(),
+        })
+    }
+}
@@ -0,0 +1,213 @@
+use std::{io::{Read, Write}, net::{SocketAddr, TcpStream, ToSocketAddrs}, time::{Duration, Instant}};
+
+use serde::Serialize;
+use smtp::{AuthCommand, Endpoint, Error as SMTPError, MIMEBody, MailBuilder, SMTPClient, SMTPClientTLS};
+
+use crate::model::{NotifyProfile, Relay};
+
+const TEST_MAIL_SUBJECT: &str = "SMTP connection test";
+const TEST_MAIL_BODY: &str = "This is a test message sent to verify the SMTP settings of the notification service.";
+
+#[derive(Serialize, Clone, Copy, Debug)]
+pub enum TestStage {
+    DNS,
+    TCP,
+    TLS,
+    Greeting,
+    EHLO,
+    Auth,
+    Send,
+}
+
+#[derive(Serialize)]
+pub struct StageReport {
+    pub stage: TestStage,
+    pub success: bool,
+    /// Milliseconds spent in this stage.
+    pub latency: u64,
+    pub detail: String,
+}
+
+/// Result of each stage of connecting to a relay, stops at the first failed stage.
+#[derive(Serialize)]
+pub struct RelayReport {
+    pub smtp_address: String,
+    pub success: bool,
+    pub stages: Vec<StageReport>,
+    /// Extensions advertised in the reply of `EHLO`.
+    pub extensions: Vec<String>,
+}
+
+/// Result of connecting to each relay of a service profile, in the order they are tried by the sender.
+#[derive(Serialize)]
+pub struct ConnectionReport {
+    /// Whether any of the relays can be used to send the notifications.
+    pub success: bool,
+    pub relays: Vec<RelayReport>,
+}
+
+impl RelayReport {
+    fn run_stage<T, F: FnOnce() -> Result<(T, String), String>>(&mut self, stage: TestStage, f: F) -> Option<T> {
+        let start = Instant::now();
+        let result = f();
+        let latency = start.elapsed().as_millis() as u64;
+
+        let (value, success, detail) = match result {
+            Ok((value, detail)) => (Some(value), true, detail),
+            Err(detail) => (None, false, detail),
+        };
+        self.success = self.success && success;
+        self.stages.push(StageReport {
+            stage,
+            success,
+            latency,
+            detail,
+        });
+        value
+    }
+}
+
+/// Connect to each relay of the profile stage by stage,
+/// and send a test mail through the first working relay if `recipient` is provided.
+pub fn test_connection(profile: &NotifyProfile, timeout: Duration, recipient: Option<&str>) -> ConnectionReport {
+    let mut report = ConnectionReport {
+        success: false,
+        relays: Vec::new(),
+    };
+    for relay in profile.relays() {
+        // The test mail is sent only once, as the notifications fail over to the next relay.
+        let recipient = recipient.filter(|_| !report.success);
+        let relay_report = test_relay(&relay, profile, timeout, recipient);
+        report.success = report.success || relay_report.success;
+        report.relays.push(relay_report);
+    }
+    report
+}
+
+fn test_relay(relay: &Relay, profile: &NotifyProfile, timeout: Duration, recipient: Option<&str>) -> RelayReport {
+    let mut report = RelayReport {
+        smtp_address: relay.smtp_address.clone(),
+        success: true,
+        stages: Vec::new(),
+        extensions: Vec::new(),
+    };
+    let endpoint = Endpoint::parse(&relay.smtp_address);
+    let host = endpoint.as_ref().map(|endpoint| endpoint.host()).unwrap_or_default();
+
+    let addr = report.run_stage(TestStage::DNS, || {
+        let endpoint = endpoint.as_ref().map_err(|err| describe_error(err))?;
+        let addrs: Vec<SocketAddr> = (endpoint.host(), endpoint.port_or_default(relay.tls))
+            .to_socket_addrs()
+            .map_err(|err| err.to_string())?
+            .collect();
+        let addr = addrs.first().cloned().ok_or("Cannot resolve address".to_string())?;
+        let resolved: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
+        Ok((addr, format!("Resolved {}", resolved.join(", "))))
+    });
+    let addr = match addr {
+        Some(addr) => addr,
+        None => return report,
+    };
+
+    let stream = report.run_stage(TestStage::TCP, || {
+        let stream = TcpStream::connect_timeout(&addr, timeout).map_err(|err| err.to_string())?;
+        stream.set_read_timeout(Some(timeout)).map_err(|err| err.to_string())?;
+        stream.set_write_timeout(Some(timeout)).map_err(|err| err.to_string())?;
+        Ok((stream, format!("Connected to {}", addr)))
+    });
+    let stream = match stream {
+        Some(stream) => stream,
+        None => return report,
+    };
+
+    if relay.tls {
+        let stream = report.run_stage(TestStage::TLS, || {
+            let stream = SMTPClientTLS::tls_handshake(stream, host).map_err(|err| describe_error(&err))?;
+            let cipher = stream.ssl().current_cipher().map(|cipher| cipher.name()).unwrap_or("unknown cipher");
+            let detail = format!("{} {}", stream.ssl().version_str(), cipher);
+            Ok((stream, detail))
+        });
+        if let Some(stream) = stream {
+            test_session(&mut report, stream, relay, profile, recipient);
+        }
+    } else {
+        test_session(&mut report, stream, relay, profile, recipient);
+    }
+
+    report
+}
+
+fn test_session<S: Read + Write>(report: &mut RelayReport, stream: S, relay: &Relay, profile: &NotifyProfile, recipient: Option<&str>) {
+    let client = report.run_stage(TestStage::Greeting, || {
+        let client = SMTPClient::greet(stream).map_err(|err| describe_error(&err))?;
+        let detail = format!("{} {}", client.greeting().code, client.greeting().text_lines.join(" "));
+        Ok((client, detail))
+    });
+    let mut client = match client {
+        Some(client) => client,
+        None => return,
+    };
+
+    let extensions = report.run_stage(TestStage::EHLO, || {
+        client.ehlo("localhost").map_err(|err| describe_error(&err))?;
+        let mut extensions: Vec<String> = client
+            .extensions()
+            .iter()
+            .map(|(name, params)| match params {
+                Some(params) => format!("{} {}", name, params),
+                None => name.clone(),
+            })
+            .collect();
+        extensions.sort();
+        let detail = format!("{} extensions advertised", extensions.len());
+        Ok((extensions, detail))
+    });
+    match extensions {
+        Some(extensions) => report.extensions = extensions,
+        None => return,
+    }
+
+    let authenticated = report.run_stage(TestStage::Auth, || {
+        let auth = AuthCommand::Plain(None, relay.username.clone(), relay.password.clone());
+        client.auth(auth).map_err(|err| describe_error(&err))?;
+        Ok(((), format!("Authenticated as {}", relay.username)))
+    });
+    if authenticated.is_none() {
+        client.quit().ok();
+        return;
+    }
+
+    if let Some(recipient) = recipient {
+        report.run_stage(TestStage::Send, || {
+            let mail = MailBuilder::new()
+                .from((&profile.name, &profile.email_address))
+                .to(recipient)
+                .subject(TEST_MAIL_SUBJECT)
+                .body(MIMEBody::new("text/plain; charset=utf-8").text(TEST_MAIL_BODY))
+                .build();
+            let replies = client
+                .send_mail(&profile.email_address, &[recipient], mail)
+                .map_err(|err| describe_error(&err))?;
+            match replies.recipients.first() {
+                Some(reply) if reply.is_positive_completion() => Ok(((), format!("Test mail sent to {}", recipient))),
+                Some(reply) => Err(format!("Recipient rejected: {} {}", reply.code, reply.text_lines.join(" "))),
+                None => Err("No reply of recipient".to_string()),
+            }
+        });
+    }
+
+    client.quit().ok();
+}
+
+fn describe_error(err: &SMTPError) -> String {
+    match err {
+        SMTPError::ErrorReply(reply) => format!("Unexpected SMTP reply: {} {}", reply.code, reply.text_lines.join(" ")),
+        SMTPError::HandshakeError(err) => describe_error(err),
+        SMTPError::IOError(err) => err.to_string(),
+        SMTPError::OpenSSLError(err) => err.to_string(),
+        SMTPError::TLSHandshakeError(err) => err.to_string(),
+        SMTPError::ExtensionNotSupported(name) => format!("Extension {} not supported", name),
+        SMTPError::OtherError(msg) => msg.clone(),
+        err => format!("{:?}", err),
+    }
+}
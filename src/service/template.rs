@@ -0,0 +1,150 @@
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+use super::Locale;
+
+/// A minimal logic-less template.
+///
+/// `{{ name }}` is substituted with the variable `name`, nested values are accessed by
+/// dot separated path, e.g. `{{ comment.author }}`. Only strings, numbers and booleans
+/// can be substituted, `null` is rendered as empty string.
+///
+/// A filter formats the value for the locale, e.g. `{{ price | number }}`,
+/// `{{ created_at | date }}` or `{{ created_at | datetime }}`.
+pub struct Template<'t> {
+    segments: Vec<Segment<'t>>,
+}
+
+enum Segment<'t> {
+    Text(&'t str),
+    Variable(Vec<&'t str>, Option<Filter>),
+}
+
+#[derive(Clone, Copy)]
+enum Filter {
+    Number,
+    Date,
+    DateTime,
+}
+
+#[derive(Clone, Copy)]
+pub enum Escape {
+    None,
+    Html,
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    /// (position)
+    UnclosedTag(usize),
+    InvalidVariable(String),
+    InvalidFilter(String),
+    MissingVariable(String),
+    InvalidValue(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::UnclosedTag(pos) => write!(f, "Unclosed tag at {}", pos),
+            TemplateError::InvalidVariable(name) => write!(f, "Invalid variable name '{}'", name),
+            TemplateError::InvalidFilter(name) => write!(f, "Unknown filter '{}'", name),
+            TemplateError::MissingVariable(name) => write!(f, "Missing variable '{}'", name),
+            TemplateError::InvalidValue(name) => write!(f, "Variable '{}' cannot be rendered", name),
+        }
+    }
+}
+
+const TAG_OPEN: &str = "{{";
+const TAG_CLOSE: &str = "}}";
+
+impl<'t> Template<'t> {
+    pub fn parse(source: &'t str) -> Result<Self, TemplateError> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+
+        while let Some(start) = rest.find(TAG_OPEN) {
+            if start > 0 {
+                segments.push(Segment::Text(&rest[..start]));
+            }
+            let tag = &rest[start + TAG_OPEN.len()..];
+            let end = tag.find(TAG_CLOSE)
+                .ok_or(TemplateError::UnclosedTag(source.len() - rest.len() + start))?;
+
+            let mut parts = tag[..end].splitn(2, '|');
+            let name = parts.next().unwrap_or_default().trim();
+            let filter = match parts.next().map(|filter| filter.trim()) {
+                None => None,
+                Some("number") => Some(Filter::Number),
+                Some("date") => Some(Filter::Date),
+                Some("datetime") => Some(Filter::DateTime),
+                Some(filter) => return Err(TemplateError::InvalidFilter(filter.to_string())),
+            };
+            let path: Vec<&str> = name.split('.').collect();
+            let valid = path.iter().all(|key| {
+                !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            });
+            if !valid {
+                return Err(TemplateError::InvalidVariable(name.to_string()));
+            }
+            segments.push(Segment::Variable(path, filter));
+
+            rest = &tag[end + TAG_CLOSE.len()..];
+        }
+        if rest.len() > 0 {
+            segments.push(Segment::Text(rest));
+        }
+
+        Ok(Self { segments })
+    }
+
+    pub fn render(&self, variables: &Map<String, Value>, escape: Escape, locale: &Locale) -> Result<String, TemplateError> {
+        let mut output = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => output.push_str(text),
+                Segment::Variable(path, filter) => {
+                    let name = || path.join(".");
+                    let mut value = variables.get(path[0]);
+                    for key in &path[1..] {
+                        value = value.and_then(|v| v.get(key));
+                    }
+                    let value = value.ok_or_else(|| TemplateError::MissingVariable(name()))?;
+                    let text = match (filter, value) {
+                        (_, Value::Null) => Some(String::new()),
+                        (Some(Filter::Number), Value::Number(num)) => Some(locale.format_number(num)),
+                        (Some(Filter::Number), _) => None,
+                        (Some(Filter::Date), value) => locale.format_date(value),
+                        (Some(Filter::DateTime), value) => locale.format_datetime(value),
+                        (None, Value::String(text)) => Some(text.clone()),
+                        (None, Value::Number(num)) => Some(num.to_string()),
+                        (None, Value::Bool(b)) => Some(b.to_string()),
+                        (None, _) => None,
+                    };
+                    let text = text.ok_or_else(|| TemplateError::InvalidValue(name()))?;
+                    match escape {
+                        Escape::None => output.push_str(&text),
+                        Escape::Html => push_html_escaped(&mut output, &text),
+                    }
+                }
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+fn push_html_escaped(output: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            c => output.push(c),
+        }
+    }
+}
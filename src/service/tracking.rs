@@ -0,0 +1,172 @@
+use mongodb::bson::oid::ObjectId;
+use openssl::{error::ErrorStack, memcmp};
+use reqwest::Url;
+
+use super::signature::sign;
+
+/// Signs and verifies the links recording the opens and clicks of the notifications,
+/// a link stays valid as long as the secret is unchanged.
+#[derive(Clone)]
+pub struct TrackingLinks {
+    /// Public URL of this service the links point to, e.g. `https://notify.example.com`.
+    base_url: String,
+    secret: String,
+}
+
+impl TrackingLinks {
+    pub fn new(base_url: &str, secret: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            secret: secret.to_string(),
+        }
+    }
+
+    /// The tracking pixel of the notification.
+    pub fn open_link(&self, message_id: &ObjectId) -> Result<String, ErrorStack> {
+        Ok(format!("{}/track/open?token={}", self.base_url, self.token("open", &message_id.to_hex())?))
+    }
+
+    /// The link redirecting to `url` after the click is recorded.
+    pub fn click_link(&self, message_id: &ObjectId, url: &str) -> Result<String, ErrorStack> {
+        let payload = format!("{}.{}", message_id.to_hex(), hex::encode(url));
+        Ok(format!("{}/track/click?token={}", self.base_url, self.token("click", &payload)?))
+    }
+
+    /// `<payload>.<hex of HMAC-SHA256 of <kind>.<payload>>`, the kind keeps a token from being used as another.
+    fn token(&self, kind: &str, payload: &str) -> Result<String, ErrorStack> {
+        let signature = sign(&self.secret, &format!("{}.{}", kind, payload))?;
+        Ok(format!("{}.{}", payload, hex::encode(signature)))
+    }
+
+    fn verify<'a>(&self, kind: &str, token: &'a str) -> Option<&'a str> {
+        let idx = token.rfind('.')?;
+        let (payload, signature) = (&token[..idx], &token[idx + 1..]);
+        let signature = hex::decode(signature).ok()?;
+        let expected = sign(&self.secret, &format!("{}.{}", kind, payload)).ok()?;
+        if signature.len() != expected.len() || !memcmp::eq(&signature, &expected) {
+            return None;
+        }
+        Some(payload)
+    }
+
+    /// The notification of a valid open token.
+    pub fn verify_open(&self, token: &str) -> Option<ObjectId> {
+        ObjectId::with_string(self.verify("open", token)?).ok()
+    }
+
+    /// The notification and the link of a valid click token.
+    pub fn verify_click(&self, token: &str) -> Option<(ObjectId, String)> {
+        let payload = self.verify("click", token)?;
+        let idx = payload.find('.')?;
+        let message_id = ObjectId::with_string(&payload[..idx]).ok()?;
+        let url = String::from_utf8(hex::decode(&payload[idx + 1..]).ok()?).ok()?;
+        Some((message_id, url))
+    }
+
+    /// Rewrite the quoted `http` and `https` links of the `href` attributes of the `<a>` tags to the click links,
+    /// and add the tracking pixel at the end of the body.
+    pub fn track_html(&self, message_id: &ObjectId, html: &str) -> Result<String, ErrorStack> {
+        // The offsets of ASCII lowercase are the same as the original.
+        let lowercase = html.to_ascii_lowercase();
+        let mut result = String::with_capacity(html.len());
+        // `html` before `offset` is copied to the result, tags before `pos` are scanned.
+        let mut offset = 0;
+        let mut pos = 0;
+        while let Some(idx) = lowercase[pos..].find("<a") {
+            let name_end = pos + idx + "<a".len();
+            // Other tags starting with `a`, e.g. `<abbr>`, or an `<a>` without attributes.
+            if !html[name_end..].starts_with(|c: char| c.is_ascii_whitespace()) {
+                pos = name_end;
+                continue;
+            }
+            let (href, tag_end) = match scan_attributes(html, name_end) {
+                Some(tag) => tag,
+                None => break,
+            };
+            if let Some((start, end)) = href {
+                let url = html[start..end].trim().replace("&amp;", "&");
+                if self.is_trackable(&url) {
+                    result.push_str(&html[offset..start]);
+                    result.push_str(&self.click_link(message_id, &url)?);
+                    offset = end;
+                }
+            }
+            pos = tag_end;
+        }
+        result.push_str(&html[offset..]);
+
+        let pixel = format!(r#"<img src="{}" width="1" height="1" alt="" style="border:0">"#, self.open_link(message_id)?);
+        match result.to_ascii_lowercase().rfind("</body>") {
+            Some(idx) => result.insert_str(idx, &pixel),
+            None => result.push_str(&pixel),
+        }
+        Ok(result)
+    }
+
+    /// Links to other sites, the links of this service e.g. unsubscribe are kept.
+    fn is_trackable(&self, url: &str) -> bool {
+        let url = match Url::parse(url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url,
+            _ => return false,
+        };
+        match Url::parse(&self.base_url) {
+            Ok(base_url) => url.origin() != base_url.origin(),
+            Err(_) => true,
+        }
+    }
+}
+
+/// Scan the attributes of a tag from `pos` after its name to the closing `>`,
+/// return the range of the quoted value of `href` if there is one, and the position after the tag.
+///
+/// `None` if the tag is not closed.
+fn scan_attributes(html: &str, mut pos: usize) -> Option<(Option<(usize, usize)>, usize)> {
+    let bytes = html.as_bytes();
+    let skip_whitespace = |mut pos: usize| {
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        pos
+    };
+    let mut href = None;
+    loop {
+        pos = skip_whitespace(pos);
+        match bytes.get(pos)? {
+            b'>' => return Some((href, pos + 1)),
+            b'/' => {
+                pos += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let name_start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() && !matches!(bytes[pos], b'=' | b'>' | b'/') {
+            pos += 1;
+        }
+        let name = &html[name_start..pos];
+        pos = skip_whitespace(pos);
+        if bytes.get(pos) != Some(&b'=') {
+            continue;
+        }
+        pos = skip_whitespace(pos + 1);
+
+        let value = match bytes.get(pos)? {
+            &quote if quote == b'"' || quote == b'\'' => {
+                let len = html[pos + 1..].find(quote as char)?;
+                let value = (pos + 1, pos + 1 + len);
+                pos = value.1 + 1;
+                Some(value)
+            }
+            _ => {
+                while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() && bytes[pos] != b'>' {
+                    pos += 1;
+                }
+                None
+            }
+        };
+        if href.is_none() && name.eq_ignore_ascii_case("href") {
+            href = value;
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use mongodb::bson::oid::ObjectId;
+use openssl::{error::ErrorStack, memcmp};
+
+use super::signature::sign;
+
+/// Signs and verifies the one-click unsubscribe links,
+/// a link stays valid as long as the secret is unchanged.
+#[derive(Clone)]
+pub struct UnsubscribeLinks {
+    /// Public URL of this service the links point to, e.g. `https://notify.example.com`.
+    base_url: String,
+    secret: String,
+}
+
+impl UnsubscribeLinks {
+    pub fn new(base_url: &str, secret: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            secret: secret.to_string(),
+        }
+    }
+
+    /// The link to unsubscribe the address from the notifications of the sender profile.
+    pub fn link(&self, sender_profile: &ObjectId, address: &str) -> Result<String, ErrorStack> {
+        Ok(format!("{}/unsubscribe?token={}", self.base_url, self.token(sender_profile, address)?))
+    }
+
+    /// `<sender_profile>.<hex of lowercase address>.<hex of HMAC-SHA256 of the former two>`
+    fn token(&self, sender_profile: &ObjectId, address: &str) -> Result<String, ErrorStack> {
+        let payload = format!("{}.{}", sender_profile.to_hex(), hex::encode(address.to_lowercase()));
+        let signature = sign(&self.secret, &payload)?;
+        Ok(format!("{}.{}", payload, hex::encode(signature)))
+    }
+
+    /// The sender profile and the address of a valid token.
+    pub fn verify(&self, token: &str) -> Option<(ObjectId, String)> {
+        let idx = token.rfind('.')?;
+        let (payload, signature) = (&token[..idx], &token[idx + 1..]);
+        let signature = hex::decode(signature).ok()?;
+        let expected = sign(&self.secret, payload).ok()?;
+        if signature.len() != expected.len() || !memcmp::eq(&signature, &expected) {
+            return None;
+        }
+
+        let idx = payload.find('.')?;
+        let sender_profile = ObjectId::with_string(&payload[..idx]).ok()?;
+        let address = String::from_utf8(hex::decode(&payload[idx + 1..]).ok()?).ok()?;
+        Some((sender_profile, address))
+    }
+}
@@ -0,0 +1,220 @@
+use std::{future::Future, io, net::IpAddr, pin::Pin, task::{Context, Poll}, time::Duration};
+
+use chrono::Utc;
+use hyper::client::{HttpConnector, connect::dns::Name};
+use hyper_tls::HttpsConnector;
+use mongodb::bson::oid::ObjectId;
+use reqwest::{Client, StatusCode, Url, header::CONTENT_TYPE};
+use serde::Serialize;
+use tower_service::Service;
+
+use super::signature::sign;
+
+/// Unix timestamp in seconds when the event is posted, included in the signature.
+const HEADER_TIMESTAMP: &str = "X-Notify-Timestamp";
+/// `sha256=<hex>` of the HMAC-SHA256 of `<timestamp>.<body>` keyed by the webhook secret.
+const HEADER_SIGNATURE: &str = "X-Notify-Signature";
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+const WEBHOOK_MAX_ATTEMPTS: u32 = 5;
+/// Delay before the first retry, doubled for each retry after.
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Published to the status stream and posted to the webhook when a notification is moved to another state.
+#[derive(Serialize, Clone, Debug)]
+pub struct NotifyEvent {
+    #[serde(skip)]
+    pub sender_profile: ObjectId,
+    pub message_id: String,
+    pub status: &'static str,
+    pub error: Option<String>,
+    pub attempts: i32,
+}
+
+#[derive(Clone)]
+pub(super) struct WebhookSender {
+    client: Client,
+    /// For the urls given by the requests, connects only to the public addresses and never follows redirects.
+    public_client: PublicClient,
+}
+
+type PublicClient = hyper::Client<HttpsConnector<HttpConnector<PublicResolver>>>;
+
+impl WebhookSender {
+    pub fn new() -> Self {
+        let client = Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .unwrap();
+
+        let mut http = HttpConnector::new_with_resolver(PublicResolver);
+        http.enforce_http(false);
+        http.set_connect_timeout(Some(WEBHOOK_TIMEOUT));
+        let mut https = HttpsConnector::new_with_connector(http);
+        https.https_only(true);
+        let public_client = hyper::Client::builder().build(https);
+
+        Self { client, public_client }
+    }
+
+    /// Post the event in background, retry with exponential backoff until the webhook responds with `2xx`.
+    /// Must be called within the tokio runtime.
+    pub fn post(&self, url: String, secret: Option<String>, event: NotifyEvent) {
+        let transport = Transport::Registered(self.client.clone());
+        tokio::spawn(async move {
+            deliver(transport, &url, secret.as_deref(), &event).await;
+        });
+    }
+
+    /// Same as `post`, but only connects to the url if its host resolves to public addresses, and the redirects are not followed.
+    /// For the urls given by the requests rather than registered on the sender profile.
+    pub fn post_public(&self, url: String, secret: Option<String>, event: NotifyEvent) {
+        let transport = Transport::Public(self.public_client.clone());
+        tokio::spawn(async move {
+            deliver(transport, &url, secret.as_deref(), &event).await;
+        });
+    }
+}
+
+/// Resolves the host of a webhook given by a request, fails if any of the addresses is not public.
+/// The connection is made to the checked addresses, so the host cannot be rebound to another address in between.
+#[derive(Clone)]
+struct PublicResolver;
+
+impl Service<Name> for PublicResolver {
+    type Response = std::vec::IntoIter<IpAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, io::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<IpAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .map(|addr| addr.ip())
+                .collect();
+            if addrs.is_empty() || !addrs.iter().all(is_public_ip) {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} resolves to a non-public address", host)));
+            }
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+enum Transport {
+    Registered(Client),
+    Public(PublicClient),
+}
+
+impl Transport {
+    async fn post(&self, url: &str, headers: &[(&str, String)], body: Vec<u8>) -> Result<StatusCode, String> {
+        match self {
+            Transport::Registered(client) => {
+                let mut request = client.post(url)
+                    .header(CONTENT_TYPE, "application/json");
+                for (name, value) in headers {
+                    request = request.header(*name, value);
+                }
+                request.body(body)
+                    .send()
+                    .await
+                    .map(|response| response.status())
+                    .map_err(|err| err.to_string())
+            }
+            Transport::Public(client) => {
+                let mut request = hyper::Request::post(url)
+                    .header(CONTENT_TYPE, "application/json");
+                for (name, value) in headers {
+                    request = request.header(*name, value);
+                }
+                let request = request.body(hyper::Body::from(body))
+                    .map_err(|err| err.to_string())?;
+                match tokio::time::timeout(WEBHOOK_TIMEOUT, client.request(request)).await {
+                    Ok(result) => result.map(|response| response.status()).map_err(|err| err.to_string()),
+                    Err(_) => Err("Timed out".to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// An `https://` url whose host is not a loopback, private or link-local address.
+pub fn is_public_webhook_url(url: &str) -> bool {
+    let url = match Url::parse(url) {
+        Ok(url) if url.scheme() == "https" && url.username().is_empty() && url.password().is_none() => url,
+        _ => return false,
+    };
+    match url.host_str() {
+        Some(host) => match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            Ok(ip) => is_public_ip(&ip),
+            Err(_) => {
+                let host = host.trim_end_matches('.').to_lowercase();
+                !host.is_empty() && host != "localhost" && !host.ends_with(".localhost")
+            }
+        },
+        None => false,
+    }
+}
+
+fn is_public_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+                || ip.is_broadcast() || ip.is_multicast() || ip.is_documentation()
+                || a == 0 || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            if ip.is_loopback() || ip.is_unspecified() || ip.is_multicast()
+                || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80 {
+                false
+            } else if let Some(ip) = ip.to_ipv4() {
+                is_public_ip(&IpAddr::V4(ip))
+            } else {
+                true
+            }
+        }
+    }
+}
+
+async fn deliver(transport: Transport, url: &str, secret: Option<&str>, event: &NotifyEvent) {
+    let body = serde_json::to_vec(event).unwrap();
+    let mut delay = WEBHOOK_RETRY_DELAY;
+
+    for attempt in 1..=WEBHOOK_MAX_ATTEMPTS {
+        let timestamp = Utc::now().timestamp();
+        let mut headers = vec![(HEADER_TIMESTAMP, timestamp.to_string())];
+        if let Some(secret) = secret {
+            let mut payload = format!("{}.", timestamp).into_bytes();
+            payload.extend_from_slice(&body);
+            match sign(secret, &payload) {
+                Ok(signature) => headers.push((HEADER_SIGNATURE, format!("sha256={}", hex::encode(signature)))),
+                Err(err) => log::error!("Failed to sign webhook event: {:?}", err),
+            }
+        }
+
+        match transport.post(url, &headers, body.clone()).await {
+            Ok(status) if status.is_success() => {
+                log::info!("Webhook event of {} delivered to {} at attempt {}", &event.message_id, url, attempt);
+                return;
+            }
+            Ok(status) => {
+                log::warn!("Webhook {} responded {} at attempt {}", url, status, attempt);
+            }
+            Err(err) => {
+                log::warn!("Failed to post webhook {} at attempt {}: {}", url, attempt, err);
+            }
+        }
+
+        if attempt < WEBHOOK_MAX_ATTEMPTS {
+            tokio::time::delay_for(delay).await;
+            delay *= 2;
+        }
+    }
+    log::error!("Give up webhook event of {} to {} after {} attempts", &event.message_id, url, WEBHOOK_MAX_ATTEMPTS);
+}
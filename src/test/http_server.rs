@@ -0,0 +1,77 @@
+use std::{collections::HashMap, io::{self, BufRead, BufReader, Read, Write}, net::{TcpListener, TcpStream}, sync::{Arc, Mutex}, thread::spawn};
+
+/// A request received by the `HTTPServer`.
+#[derive(Clone, Default, Debug)]
+pub struct ReceivedRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are in lowercase.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// A minimal HTTP server listening on a local port, stands in for a webhook in tests.
+pub struct HTTPServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<ReceivedRequest>>>,
+}
+
+impl HTTPServer {
+    /// Start a server responds the requests with `statuses` in order, and `200` after all of them are used.
+    pub fn start_with_statuses(statuses: Vec<u16>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/webhook", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        spawn(move || {
+            let mut statuses = statuses.into_iter();
+            for stream in listener.incoming() {
+                if let Ok(stream) = stream {
+                    let status = statuses.next().unwrap_or(200);
+                    handle_request(stream, status, &received).ok();
+                }
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn handle_request(stream: TcpStream, status: u16, requests: &Mutex<Vec<ReceivedRequest>>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut request_line = line.trim_end().split(' ');
+    let mut request = ReceivedRequest {
+        method: request_line.next().unwrap_or_default().to_string(),
+        path: request_line.next().unwrap_or_default().to_string(),
+        ..Default::default()
+    };
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some(idx) = line.find(':') {
+            request.headers.insert(line[..idx].trim().to_lowercase(), line[idx + 1..].trim().to_string());
+        }
+    }
+
+    let length = request.headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body)?;
+    requests.lock().unwrap().push(request);
+
+    writer.write_all(format!("HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).as_bytes())
+}
@@ -1,24 +1,33 @@
 mod helper;
+mod http_server;
+mod smtp_server;
 mod test_access_service;
 mod test_auth;
 mod test_service;
 mod test_notify;
+mod test_smtp_pool;
+mod test_template;
 
 use actix_web::{App, dev::{MessageBody, ServiceRequest, ServiceResponse}, middleware::Logger, test, web::Json};
 use actix_http::Request;
 use env_logger::Env;
 use futures::executor::block_on;
 use mongodb::bson::oid::ObjectId;
-use std::{time::Duration, thread::spawn};
+use std::{net::SocketAddr, time::Duration, thread::spawn};
 use actix_rt::time;
 
-use crate::{service::EmailNotifyService, controller, middleware, model::ServiceRecord, model::{AccessManagerProfile, Model, Service, ServiceManagerProfile, Access, UserProfile}};
+use crate::{service::{BounceReceiver, EmailNotifyService, TrackingLinks, UnsubscribeLinks, VerpAddresses}, controller, middleware, model::ServiceRecord, model::{AccessManagerProfile, Model, Service, ServiceManagerProfile, Access, UserProfile}};
 
 const TEST_ADDR: &str = "localhost:3000";
 const TEST_DB_ADDR: &str = "mongodb://localhost";
 const TEST_DB_NAME: &str = "sar-notify-test";
 const TEST_ROOT_UID: &str = "test-root";
 const TEST_ROOT_SECRET: &str = "TEST_SECRET";
+const TEST_PUBLIC_URL: &str = "http://localhost:3000";
+const TEST_UNSUBSCRIBE_SECRET: &str = "TEST_UNSUBSCRIBE_SECRET";
+const TEST_TRACKING_SECRET: &str = "TEST_TRACKING_SECRET";
+const TEST_BOUNCE_DOMAIN: &str = "bounce.sardinefish.com";
+const TEST_BOUNCE_SECRET: &str = "TEST_BOUNCE_SECRET";
 
 
 type AppType = impl actix_web::dev::Service<Request = Request, Response= ServiceResponse, Error = actix_web::Error>;
@@ -27,11 +36,33 @@ async fn config_app() -> AppType {
     // env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
     
     let model = Model::new(TEST_DB_ADDR, TEST_DB_NAME).await.unwrap();
-    let notify_service = EmailNotifyService::new(model.clone(), Duration::from_millis(300));
+    let notify_service = notify_service(&model);
+    init_app(model, notify_service).await
+}
+
+/// Also start a bounce receiver for `TEST_BOUNCE_DOMAIN` on a local port, return the address of the receiver.
+async fn config_app_with_bounce() -> (AppType, SocketAddr) {
+    let model = Model::new(TEST_DB_ADDR, TEST_DB_NAME).await.unwrap();
+    let notify_service = notify_service(&model);
+    let bounce_addr = BounceReceiver::new(model.clone(), &notify_service, TEST_BOUNCE_DOMAIN)
+        .listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    (init_app(model, notify_service).await, bounce_addr)
+}
+
+fn notify_service(model: &Model) -> EmailNotifyService {
+    let unsubscribe = UnsubscribeLinks::new(TEST_PUBLIC_URL, TEST_UNSUBSCRIBE_SECRET);
+    let tracking = TrackingLinks::new(TEST_PUBLIC_URL, TEST_TRACKING_SECRET);
+    let verp = VerpAddresses::new(TEST_BOUNCE_SECRET);
+    EmailNotifyService::new(model.clone(), Duration::from_millis(300), Some(unsubscribe), Some(tracking), Some(verp))
+}
+
+async fn init_app(model: Model, notify_service: EmailNotifyService) -> impl actix_web::dev::Service<Request = Request, Response = ServiceResponse, Error = actix_web::Error> {
     test::init_service(
     App::new()
-            .data(model.clone())
-            .data(notify_service.clone())
+            .data(model)
+            .data(notify_service)
             .wrap(middleware::authentication())
             .wrap(middleware::error_formatter())
             .configure(controller::config)
@@ -59,7 +90,12 @@ async fn init() {
 
 #[actix_rt::test]
 async fn test_service_setup() {
-    let server = super::start_server(TEST_DB_ADDR, TEST_DB_NAME, TEST_ADDR).await.unwrap();
+    let server = super::start_server(super::ServerContext {
+        db_addr: TEST_DB_ADDR,
+        db_name: TEST_DB_NAME,
+        listen_addr: TEST_ADDR,
+        ..Default::default()
+    }).await.unwrap();
 
     let srv = server.clone();
     let thread = spawn(move || {
@@ -0,0 +1,130 @@
+use std::{io::{self, BufRead, BufReader, Write}, net::{Shutdown, TcpListener, TcpStream}, sync::{Arc, Mutex}, thread::spawn};
+
+/// A mail received by the `SMTPServer`.
+#[derive(Clone, Default, Debug)]
+pub struct ReceivedMail {
+    pub mail_from: String,
+    pub rcpt_to: Vec<String>,
+    pub data: String,
+}
+
+/// A minimal SMTP server listening on a local port, stands in for a relay in tests.
+/// Any authentication is accepted.
+pub struct SMTPServer {
+    pub addr: String,
+    mails: Arc<Mutex<Vec<ReceivedMail>>>,
+    connections: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl SMTPServer {
+    /// Start a server accepts all recipients.
+    pub fn start() -> Self {
+        Self::start_with_rcpt_reply("250 OK")
+    }
+
+    /// Start a server replies `RCPT` with `rcpt_reply`, e.g. `451 Try again later` to defer all mails.
+    pub fn start_with_rcpt_reply(rcpt_reply: &'static str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mails = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(Mutex::new(Vec::new()));
+
+        let received = mails.clone();
+        let accepted = connections.clone();
+        spawn(move || {
+            for stream in listener.incoming() {
+                if let Ok(stream) = stream {
+                    if let Ok(cloned) = stream.try_clone() {
+                        accepted.lock().unwrap().push(cloned);
+                    }
+                    let received = received.clone();
+                    spawn(move || handle_session(stream, rcpt_reply, received).ok());
+                }
+            }
+        });
+
+        Self { addr, mails, connections }
+    }
+
+    pub fn mails(&self) -> Vec<ReceivedMail> {
+        self.mails.lock().unwrap().clone()
+    }
+
+    /// Number of the connections ever accepted.
+    pub fn connections(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    /// Drop all the open connections as if the server timed them out.
+    pub fn disconnect_all(&self) {
+        for stream in self.connections.lock().unwrap().iter() {
+            stream.shutdown(Shutdown::Both).ok();
+        }
+    }
+}
+
+fn handle_session(stream: TcpStream, rcpt_reply: &str, mails: Arc<Mutex<Vec<ReceivedMail>>>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    writer.write_all(b"220 localhost ESMTP\r\n")?;
+
+    let mut mail = ReceivedMail::default();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let command = line.trim_end().to_string();
+        let verb = command.split(' ').next().unwrap_or_default().to_uppercase();
+        let reply = match verb.as_str() {
+            "EHLO" => "250-localhost\r\n250 AUTH PLAIN LOGIN".to_string(),
+            "HELO" => "250 localhost".to_string(),
+            "AUTH" => "235 Authentication succeeded".to_string(),
+            "NOOP" => "250 OK".to_string(),
+            "RSET" => {
+                mail = ReceivedMail::default();
+                "250 OK".to_string()
+            }
+            "MAIL" => {
+                mail.mail_from = path(&command);
+                "250 OK".to_string()
+            }
+            "RCPT" => {
+                if rcpt_reply.starts_with('2') {
+                    mail.rcpt_to.push(path(&command));
+                }
+                rcpt_reply.to_string()
+            }
+            "DATA" => {
+                writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")?;
+                loop {
+                    line.clear();
+                    if reader.read_line(&mut line)? == 0 {
+                        return Ok(());
+                    }
+                    if line == ".\r\n" {
+                        break;
+                    }
+                    mail.data.push_str(line.strip_prefix('.').unwrap_or(&line));
+                }
+                mails.lock().unwrap().push(std::mem::take(&mut mail));
+                "250 OK".to_string()
+            }
+            "QUIT" => {
+                writer.write_all(b"221 Bye\r\n")?;
+                return Ok(());
+            }
+            _ => "502 Command not implemented".to_string(),
+        };
+        writer.write_all(format!("{}\r\n", reply).as_bytes())?;
+    }
+}
+
+/// The address in `MAIL FROM:<path>` or `RCPT TO:<path>`.
+fn path(command: &str) -> String {
+    match (command.find('<'), command.find('>')) {
+        (Some(start), Some(end)) if start < end => command[start + 1..end].to_string(),
+        _ => String::new(),
+    }
+}
@@ -1,7 +1,7 @@
 use actix_http::http::StatusCode;
 use actix_rt;
 use actix_web::{dev::ServiceResponse, test::TestRequest};
-use model::{Access, AccessManagerProfile, ServiceManagerProfile};
+use model::{Access, AccessManagerProfile, NotifyProfile, ServiceManagerProfile};
 
 use crate::{model, test_case};
 
@@ -41,6 +41,27 @@ async fn request_update_service(app: &mut AppType, auth: &UserAuth, uid: &str, s
         .await
 }
 
+fn notify_service(sender: &str, default: bool) -> model::Service {
+    model::Service::EmailNotify(NotifyProfile {
+        smtp_address: "localhost:25".to_string(),
+        tls: false,
+        name: "Display Name".to_string(),
+        username: "user@example.com".to_string(),
+        password: "password".to_string(),
+        email_address: "user@example.com".to_string(),
+        max_attachments_size: None,
+        sender: Some(sender.to_string()),
+        default,
+        fallback_relays: Vec::new(),
+        rate_limit: None,
+        webhook_url: None,
+        webhook_secret: None,
+        retention: None,
+        verp_domain: None,
+        tracking: false,
+    })
+}
+
 async fn request_delete_service(app: &mut AppType, auth: &UserAuth, uid: &str, service_id: &str) -> ServiceResponse {
     TestRequest::delete()
         .uri(&format!("/service/profile/{}/{}", uid, service_id))
@@ -362,4 +383,52 @@ async fn test_service_delete() {
     });
 
     cleanup(app, root, vec![admin, another_admin]).await;
-}
\ No newline at end of file
+}
+
+#[actix_rt::test]
+async fn test_service_default_sender() {
+    let mut app = config_app().await;
+    let root = make_root_access();
+
+    let admin = add_user(&mut app, &root, &UserInfo::new_for_test(Access::Admin)).await;
+
+    let default_profile: ServiceProfile = test_case!("Add default sender profile should be ok", async {
+        request_add_service(&mut app, &root, &admin.uid, &notify_service("first", true))
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await
+    });
+
+    test_case!("Add another default sender profile should be bad request", async {
+        request_add_service(&mut app, &root, &admin.uid, &notify_service("second", true))
+        .await
+        .expect_status(StatusCode::BAD_REQUEST)
+        .expect_error_data()
+        .await;
+    });
+
+    let profile: ServiceProfile = test_case!("Add non-default sender profile should be ok", async {
+        request_add_service(&mut app, &root, &admin.uid, &notify_service("second", false))
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await
+    });
+
+    test_case!("Update another sender profile to be default should be bad request", async {
+        request_update_service(&mut app, &root, &admin.uid, &profile.service_id, &notify_service("second", true))
+        .await
+        .expect_status(StatusCode::BAD_REQUEST)
+        .expect_error_data()
+        .await;
+    });
+
+    test_case!("Update the default sender profile should be ok", async {
+        request_update_service(&mut app, &root, &admin.uid, &default_profile.service_id, &notify_service("first", true))
+        .await
+        .expect_status(StatusCode::OK);
+    });
+
+    cleanup(app, root, vec![admin]).await;
+}
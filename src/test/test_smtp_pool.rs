@@ -0,0 +1,112 @@
+use std::{thread::sleep, time::Duration};
+
+use mongodb::bson::oid::ObjectId;
+use smtp::{MIMEBody, MailBuilder, mail::MailData};
+
+use crate::{model::Relay, service::SMTPPool};
+
+use super::smtp_server::SMTPServer;
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn relay(server: &SMTPServer) -> Relay {
+    Relay {
+        smtp_address: server.addr.clone(),
+        tls: false,
+        username: "user@example.com".to_string(),
+        password: "password".to_string(),
+    }
+}
+
+fn mail() -> MailData {
+    MailBuilder::new()
+        .from("user@example.com")
+        .to("test@sardinefish.com")
+        .subject("Test Notification")
+        .body(MIMEBody::new("text/plain").text("The text body of an email notification."))
+        .build()
+}
+
+#[test]
+fn test_smtp_pool_reuse() {
+    let server = SMTPServer::start();
+    let relay = relay(&server);
+    let service_id = ObjectId::new();
+    let mut pool = SMTPPool::new(TIMEOUT, IDLE_TIMEOUT);
+
+    for _ in 0..3 {
+        let (recipients, data) = pool.send(&service_id, &relay, "user@example.com", mail()).unwrap();
+        assert_eq!(recipients.len(), 1);
+        assert!(data.is_some());
+    }
+    assert_eq!(server.mails().len(), 3);
+    assert_eq!(server.connections(), 1);
+
+    // Sessions of different profiles are not shared.
+    pool.send(&ObjectId::new(), &relay, "user@example.com", mail()).unwrap();
+    assert_eq!(server.connections(), 2);
+
+    pool.close_all();
+}
+
+#[test]
+fn test_smtp_pool_reconnect() {
+    let server = SMTPServer::start();
+    let relay = relay(&server);
+    let service_id = ObjectId::new();
+    let mut pool = SMTPPool::new(TIMEOUT, IDLE_TIMEOUT);
+
+    pool.send(&service_id, &relay, "user@example.com", mail()).unwrap();
+    server.disconnect_all();
+    // The NOOP before reusing the session fails, a new session is opened.
+    pool.send(&service_id, &relay, "user@example.com", mail()).unwrap();
+    assert_eq!(server.connections(), 2);
+
+    server.disconnect_all();
+    // The dead session is evicted by the keepalive.
+    pool.keep_alive();
+    pool.send(&service_id, &relay, "user@example.com", mail()).unwrap();
+    assert_eq!(server.connections(), 3);
+    assert_eq!(server.mails().len(), 3);
+
+    // Alive sessions are kept.
+    pool.keep_alive();
+    pool.send(&service_id, &relay, "user@example.com", mail()).unwrap();
+    assert_eq!(server.connections(), 3);
+
+    pool.close_all();
+}
+
+#[test]
+fn test_smtp_pool_idle_timeout() {
+    let server = SMTPServer::start();
+    let relay = relay(&server);
+    let service_id = ObjectId::new();
+    let mut pool = SMTPPool::new(TIMEOUT, Duration::from_millis(100));
+
+    pool.send(&service_id, &relay, "user@example.com", mail()).unwrap();
+    sleep(Duration::from_millis(200));
+    pool.send(&service_id, &relay, "user@example.com", mail()).unwrap();
+    assert_eq!(server.connections(), 2);
+
+    sleep(Duration::from_millis(200));
+    pool.keep_alive();
+    pool.send(&service_id, &relay, "user@example.com", mail()).unwrap();
+    assert_eq!(server.connections(), 3);
+
+    pool.close_all();
+}
+
+#[test]
+fn test_smtp_pool_connect_failed() {
+    let relay = Relay {
+        smtp_address: "127.0.0.1:1".to_string(),
+        tls: false,
+        username: "user@example.com".to_string(),
+        password: "password".to_string(),
+    };
+    let mut pool = SMTPPool::new(TIMEOUT, IDLE_TIMEOUT);
+
+    assert!(pool.send(&ObjectId::new(), &relay, "user@example.com", mail()).is_err());
+}
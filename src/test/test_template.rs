@@ -0,0 +1,382 @@
+use actix_http::http::StatusCode;
+use actix_rt;
+use actix_web::{dev::ServiceResponse, test::TestRequest};
+use serde::{Serialize, Deserialize};
+
+use crate::{model::{Access, NotifyProfile, Service}, test_case};
+
+use super::{AppType, config_app, test_access_service::{UserAuth, UserInfo, add_user, cleanup, make_root_access}, test_service::request_add_service};
+use super::helper::*;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+struct Template {
+    name: String,
+    locale: Option<String>,
+    version: i32,
+    subject: String,
+    html: Option<String>,
+    text: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+struct TemplateContent {
+    subject: String,
+    html: Option<String>,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PubNotifyInfo {
+    message_id: String,
+}
+
+async fn request_set_template(app: &mut AppType, auth: &UserAuth, name: &str, content: &TemplateContent) -> ServiceResponse {
+    TestRequest::put()
+        .uri(&format!("/notify/template/{}", name))
+        .auth(&auth.uid, &auth.secret)
+        .set_json(content)
+        .send_request(app)
+        .await
+}
+
+async fn request_get_template(app: &mut AppType, auth: &UserAuth, uri: &str) -> ServiceResponse {
+    TestRequest::get()
+        .uri(uri)
+        .auth(&auth.uid, &auth.secret)
+        .send_request(app)
+        .await
+}
+
+async fn request_delete_template(app: &mut AppType, auth: &UserAuth, name: &str) -> ServiceResponse {
+    TestRequest::delete()
+        .uri(&format!("/notify/template/{}", name))
+        .auth(&auth.uid, &auth.secret)
+        .send_request(app)
+        .await
+}
+
+async fn send_notification(app: &mut AppType, auth: &UserAuth, request: serde_json::Value) -> ServiceResponse {
+    TestRequest::post()
+        .uri("/notify/queue")
+        .auth(&auth.uid, &auth.secret)
+        .set_json(&request)
+        .send_request(app)
+        .await
+}
+
+#[actix_rt::test]
+async fn test_template() {
+    let mut app = config_app().await;
+    let root = make_root_access();
+
+    let admin = add_user(&mut app, &root, &UserInfo::new_for_test(Access::Admin)).await;
+
+    request_add_service(&mut app, &root, &admin.uid, &Service::EmailNotify(NotifyProfile {
+        smtp_address: "192.0.2.1".to_string(),
+        tls: false,
+        name: "Display Name".to_string(),
+        username: "user@example.com".to_string(),
+        password: "password".to_string(),
+        email_address: "user@example.com".to_string(),
+        max_attachments_size: None,
+        sender: None,
+        default: false,
+        fallback_relays: Vec::new(),
+        rate_limit: None,
+        webhook_url: None,
+        webhook_secret: None,
+        retention: None,
+        verp_domain: None,
+        tracking: false,
+    }))
+    .await
+    .expect_status(StatusCode::OK);
+
+    let content = TemplateContent {
+        subject: "Build {{ build.id }} {{ status }}".to_string(),
+        html: Some("<p>Hi {{ name }}, build {{ build.id }} {{ status }}.</p>".to_string()),
+        text: None,
+    };
+
+    test_case!("Set template without service profile should be forbidden", async {
+        request_set_template(&mut app, &root, "build-failed", &content)
+        .await
+        .expect_status(StatusCode::FORBIDDEN)
+        .expect_error_data()
+        .await;
+    });
+
+    test_case!("Set template with invalid syntax should be bad request", async {
+        let mut content = content.clone();
+        content.html = Some("<p>Hi {{ name </p>".to_string());
+        request_set_template(&mut app, &admin, "build-failed", &content)
+        .await
+        .expect_status(StatusCode::BAD_REQUEST)
+        .expect_error_data()
+        .await;
+    });
+
+    test_case!("Set template with invalid name should be bad request", async {
+        request_set_template(&mut app, &admin, "build failed", &content)
+        .await
+        .expect_status(StatusCode::BAD_REQUEST)
+        .expect_error_data()
+        .await;
+    });
+
+    test_case!("Set template should be ok with version 1", async {
+        let result: Template = request_set_template(&mut app, &admin, "build-failed", &content)
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert_eq!(result.version, 1);
+    });
+
+    test_case!("Update template should be ok with version 2", async {
+        let mut content = content.clone();
+        content.text = Some("Hi {{ name }}, build {{ build.id }} {{ status }}.".to_string());
+        let result: Template = request_set_template(&mut app, &admin, "build-failed", &content)
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert_eq!(result.version, 2);
+    });
+
+    test_case!("Get template should be ok with the latest version", async {
+        let result: Template = request_get_template(&mut app, &admin, "/notify/template/build-failed")
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert_eq!(result.version, 2);
+        assert!(result.text.is_some());
+    });
+
+    test_case!("Get template of specific version should be ok", async {
+        let result: Template = request_get_template(&mut app, &admin, "/notify/template/build-failed?version=1")
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert_eq!(result.version, 1);
+        assert_eq!(result.text, None);
+    });
+
+    test_case!("List templates should be ok with the latest versions", async {
+        let result: Vec<Template> = request_get_template(&mut app, &admin, "/notify/template")
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].version, 2);
+    });
+
+    test_case!("Send notification with template should be ok", async {
+        let result: PubNotifyInfo = send_notification(&mut app, &admin, serde_json::json!({
+            "to": "test@sardinefish.com",
+            "template": "build-failed",
+            "variables": {
+                "name": "<SardineFish>",
+                "status": "failed",
+                "build": { "id": 42 },
+            },
+        }))
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert!(!result.message_id.is_empty());
+    });
+
+    test_case!("Send notification with missing template variable should be bad request", async {
+        send_notification(&mut app, &admin, serde_json::json!({
+            "to": "test@sardinefish.com",
+            "template": "build-failed",
+            "variables": {
+                "name": "SardineFish",
+            },
+        }))
+        .await
+        .expect_status(StatusCode::BAD_REQUEST)
+        .expect_error_data()
+        .await;
+    });
+
+    test_case!("Send notification with non-exists template should be bad request", async {
+        send_notification(&mut app, &admin, serde_json::json!({
+            "to": "test@sardinefish.com",
+            "template": "non-exists",
+        }))
+        .await
+        .expect_status(StatusCode::BAD_REQUEST)
+        .expect_error_data()
+        .await;
+    });
+
+    let localized = TemplateContent {
+        subject: "构建 {{ build.id }} {{ status }}".to_string(),
+        html: None,
+        text: Some("{{ name }}，构建 {{ build.id }} 于 {{ build.time | datetime }} {{ status }}。".to_string()),
+    };
+
+    test_case!("Set localized template variant should be ok with version 1", async {
+        let result: Template = request_set_template(&mut app, &admin, "build-failed?locale=zh_cn", &localized)
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert_eq!(result.locale, Some("zh-CN".to_string()));
+        assert_eq!(result.version, 1);
+    });
+
+    test_case!("Set template variant with invalid locale should be bad request", async {
+        request_set_template(&mut app, &admin, "build-failed?locale=zh--CN", &localized)
+        .await
+        .expect_status(StatusCode::BAD_REQUEST)
+        .expect_error_data()
+        .await;
+    });
+
+    test_case!("Get localized template variant should be ok", async {
+        let result: Template = request_get_template(&mut app, &admin, "/notify/template/build-failed?locale=zh-CN")
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert_eq!(result.locale, Some("zh-CN".to_string()));
+        assert_eq!(result.subject, localized.subject);
+    });
+
+    test_case!("List templates should be ok with all variants", async {
+        let result: Vec<Template> = request_get_template(&mut app, &admin, "/notify/template")
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert_eq!(result.len(), 2);
+    });
+
+    test_case!("Send notification with localized template should be ok", async {
+        let result: PubNotifyInfo = send_notification(&mut app, &admin, serde_json::json!({
+            "to": "test@sardinefish.com",
+            "template": "build-failed",
+            "locale": "zh-CN",
+            "variables": {
+                "name": "SardineFish",
+                "status": "失败",
+                "build": { "id": 42, "time": "2020-12-01T08:00:00+08:00" },
+            },
+        }))
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert!(!result.message_id.is_empty());
+    });
+
+    test_case!("Send notification with unformattable variable should be bad request", async {
+        send_notification(&mut app, &admin, serde_json::json!({
+            "to": "test@sardinefish.com",
+            "template": "build-failed",
+            "locale": "zh-CN",
+            "variables": {
+                "name": "SardineFish",
+                "status": "失败",
+                "build": { "id": 42, "time": "yesterday" },
+            },
+        }))
+        .await
+        .expect_status(StatusCode::BAD_REQUEST)
+        .expect_error_data()
+        .await;
+    });
+
+    test_case!("Send notification with fallback to default template variant should be ok", async {
+        let result: PubNotifyInfo = send_notification(&mut app, &admin, serde_json::json!({
+            "to": "test@sardinefish.com",
+            "template": "build-failed",
+            "locale": "en-US",
+            "variables": {
+                "name": "SardineFish",
+                "status": "failed",
+                "build": { "id": 42 },
+            },
+        }))
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert!(!result.message_id.is_empty());
+    });
+
+    test_case!("Send notification with invalid locale should be bad request", async {
+        send_notification(&mut app, &admin, serde_json::json!({
+            "to": "test@sardinefish.com",
+            "template": "build-failed",
+            "locale": "not a locale",
+            "variables": {},
+        }))
+        .await
+        .expect_status(StatusCode::BAD_REQUEST)
+        .expect_error_data()
+        .await;
+    });
+
+    let invoice = TemplateContent {
+        subject: "Invoice".to_string(),
+        html: None,
+        text: Some("Total: {{ total | number }}".to_string()),
+    };
+
+    test_case!("Set template variant with script subtag should normalize the case", async {
+        let result: Template = request_set_template(&mut app, &admin, "invoice?locale=zh_hans_cn", &invoice)
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert_eq!(result.locale, Some("zh-Hans-CN".to_string()));
+    });
+
+    test_case!("Preview notification with large number should format it without exponent", async {
+        let result: serde_json::Value = TestRequest::post()
+        .uri("/notify/preview")
+        .auth(&admin.uid, &admin.secret)
+        .set_json(&serde_json::json!({
+            "to": "test@sardinefish.com",
+            "template": "invoice",
+            "locale": "zh-Hans-CN",
+            "variables": { "total": 1e21 },
+        }))
+        .send_request(&mut app)
+        .await
+        .expect_status(StatusCode::OK)
+        .into_json()
+        .await;
+        assert!(result["message"].as_str().unwrap().contains("Total: 1,000,000,000,000,000,000,000"));
+    });
+
+    test_case!("Delete template should be ok", async {
+        request_delete_template(&mut app, &admin, "build-failed")
+        .await
+        .expect_status(StatusCode::OK);
+    });
+
+    test_case!("Get deleted template should be 404", async {
+        request_get_template(&mut app, &admin, "/notify/template/build-failed")
+        .await
+        .expect_status(StatusCode::NOT_FOUND)
+        .expect_error_data()
+        .await;
+    });
+
+    test_case!("Delete non-exists template should be no content", async {
+        request_delete_template(&mut app, &admin, "build-failed")
+        .await
+        .expect_status(StatusCode::NO_CONTENT);
+    });
+
+    cleanup(app, root, vec![admin]).await;
+}
@@ -1,12 +1,32 @@
 use std::mem::discriminant;
 
 use futures::Future;
+use serde::{Deserialize, Deserializer};
 use std::pin::Pin;
 
 pub fn variant_eq<T>(a: &T, b: &T) -> bool {
     discriminant(a) == discriminant(b)
 }
 
+/// Deserialize a field which can be either a single value or an array of values.
+pub fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => Ok(vec![value]),
+        OneOrMany::Many(values) => Ok(values),
+    }
+}
+
 pub type FutureRtnT<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
 
 pub mod assert {